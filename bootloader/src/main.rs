@@ -10,76 +10,113 @@ extern crate uefi;
 extern crate option_parser;
 extern crate boot_common;
 
-use elf_rs::Elf;
-
-use uefi::{ EFIHandle, SimpleTextOutputInterface };
-use uefi::{ EFILoadedImageProtocol, LOADED_IMAGE_GUID };
+use elf_rs::{ Elf, ProgramType };
+
+use uefi::{ EFIHandle, EFIStatus, EFIError, EFIResult };
+use uefi::{ SimpleTextOutputInterface };
+use uefi::{ EFILoadedImageProtocol, OpenProtocolAttributes, ProtocolGuard };
+use uefi::{ EFIDevicePathProtocol };
+use uefi::device_path::{ DeviceNode, PartitionSignature, DevicePathBuf };
+use uefi::image::{ LoadImageSource, ImageExit };
 use uefi::{ SystemTable };
-
-use uefi::graphics::{ EFIGraphicsOutputProtocol, GRAPHICS_OUTPUT_PROTOCOL_GUID };
-use uefi::fs::{ EFISimpleFilesystem, EFIFileHandle, SIMPLE_FILESYSTEM_GUID };
-use uefi::memory::{ EFIMemoryMap, EFIMemoryType, EFIAllocateType };
+use uefi::{ EFIGuid };
+use uefi::string::{ CStr16Buf };
+use uefi::runtime::{ RuntimeServices, EFIResetType };
+use uefi::variable::{ VariableStore, EFIVariableAttributes };
+
+use uefi::graphics::{ EFIGraphicsOutputProtocol, BltOperation, BltPixel };
+use uefi::graphics::{ EFIGraphicsOutputInfo, EFIGraphicsPixelFormat };
+use uefi::fs::{ EFISimpleFilesystem, Directory, FileMode, Path };
+use uefi::input::{ EFIInputKey };
+use uefi::event::{ EFITimerDelay, TIMER_TICKS_PER_SECOND };
+use uefi::memory::{ EFIMemoryType, PhysRange, PAGE_SIZE, pages_for };
+use uefi::memory::{ page_align_up };
+use uefi::memory::{ AllocateType };
+use uefi::memory::{ MemoryMap, MemoryClass, MemoryDescriptor };
 
 use option_parser::{ OptionParser, Category };
 
-use boot_common::{ BootInfo };
+use boot_common::{ BootInfo, PixelFormat, PixelLayout };
+use boot_common::{ MemoryRegions, MemoryRegion, MemoryRegionKind };
+use boot_common::memory::build_memory_map;
 
 use core::panic::PanicInfo;
 
 use alloc::alloc::{ GlobalAlloc, Layout };
 use alloc::string::{ String, ToString };
+use alloc::format;
 use alloc::vec::Vec;
 
+/// The most output we keep for the boot log, the output after the limit
+/// is only printed
+const BOOT_LOG_LIMIT: usize = 64 * 1024;
+
 struct TextWriter<'a> {
     output: &'a SimpleTextOutputInterface,
+
+    /// Everything we have printed, written to the boot log if the
+    /// options wants one. The buffer is allocated up front so printing
+    /// never allocates
+    log: Option<String>,
 }
 
 impl<'a> TextWriter<'a> {
     fn new(output: &'a SimpleTextOutputInterface) -> Self {
         Self {
-            output
+            output,
+            log: Some(String::with_capacity(BOOT_LOG_LIMIT)),
         }
     }
 
-    fn print(&mut self, s: &str) {
-        let mut arr = [0u16; 1024];
-        let mut p = 0;
-        for c in s.bytes() {
-            if c == b'\n' {
-                arr[p] = b'\r' as u16;
-                p += 1;
-
-                // TODO(patrik): Check 'p' for overflow and flush the buffer
+    /// Stop adding the output to the log and return what we have logged
+    fn stop_log(&mut self) -> Option<String> {
+        self.log.take()
+    }
 
-                arr[p] = b'\n' as u16;
-                p += 1;
+    fn print(&mut self, s: &str) -> EFIResult<()> {
+        if let Some(log) = &mut self.log {
+            // Only keep what fits so the log never grows
+            let mut length = s.len().min(BOOT_LOG_LIMIT - log.len());
+            while !s.is_char_boundary(length) {
+                length -= 1;
+            }
 
-                // TODO(patrik): Check 'p' for overflow and flush the buffer
+            log.push_str(&s[..length]);
+        }
 
-                continue;
+        // Convert the string in chunks so there is no limit on the
+        // length and we don't need the allocator
+        let mut buffer = CStr16Buf::<256>::new();
+        for c in s.chars() {
+            // Make room for the '\r' and the character
+            if buffer.remaining() < 2 {
+                self.output.output_string(&buffer)?;
+                buffer.clear();
             }
 
-            arr[p] = c as u16;
-            p += 1;
+            if c == '\n' {
+                buffer.push('\r')?;
+            }
 
-            if p >= arr.len() {
-                // TODO(patrik): Flush the buffer
+            // The console can only show UCS-2 characters
+            if buffer.push(c).is_err() {
+                buffer.push(core::char::REPLACEMENT_CHARACTER)?;
             }
         }
 
-        self.output.output_string(&arr);
+        self.output.output_string(&buffer)
     }
 }
 
 impl<'a> core::fmt::Write for TextWriter<'a> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.print(s);
-        Ok(())
+        self.print(s).map_err(|_| core::fmt::Error)
     }
 }
 
 static mut WRITER: Option<TextWriter> = None;
 static mut TABLE: Option<SystemTable<'static>> = None;
+static mut RUNTIME_SERVICES: Option<&'static RuntimeServices> = None;
 
 macro_rules! print {
     ($($arg:tt)*) => ({
@@ -87,7 +124,9 @@ macro_rules! print {
 
         unsafe {
             match WRITER.as_mut() {
-                Some(w) => w.write_fmt(format_args!($($arg)*)).unwrap(),
+                // NOTE(patrik): Ignore the error, we can't report it
+                // anyway if the console is broken
+                Some(w) => { let _ = w.write_fmt(format_args!($($arg)*)); }
                 None => {},
             }
         }
@@ -104,17 +143,21 @@ struct Allocator;
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         //println!("[DEBUG]: Allocate {} bytes", layout.size());
-        let mut buffer = core::ptr::null_mut();
-        TABLE.unwrap()
-            .boot_services.allocate_pool(EFIMemoryType::BootServicesData,
-                                         layout.size(), &mut buffer);
-
-        buffer
+        // Returning null tells the alloc crate that the allocation failed,
+        // there is no table after we have exited the boot services
+        match TABLE {
+            Some(table) => table.boot_services
+                .allocate_pool(EFIMemoryType::BootServicesData, layout.size())
+                .unwrap_or(core::ptr::null_mut()),
+            None => core::ptr::null_mut(),
+        }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
         //println!("[DEBUG]: Deallocate {} bytes", layout.size());
-        TABLE.unwrap().boot_services.free_pool(ptr);
+        if let Some(table) = TABLE {
+            let _ = table.boot_services.free_pool(ptr);
+        }
     }
 }
 
@@ -126,60 +169,585 @@ fn alloc_error_handler(layout: Layout) -> ! {
     panic!("allocation error: {:?}", layout)
 }
 
-fn loaded_image<'a>(table: &SystemTable,
-                    handle: EFIHandle) -> &'a EFILoadedImageProtocol<'a> {
-    let loaded_image_ptr =
-        table.boot_services.handle_protocol(handle, &LOADED_IMAGE_GUID);
+/// Open the LoadedImage protocol for our image, the protocol is closed
+/// when the guard is dropped
+fn loaded_image<'a>(table: &'a SystemTable, image_handle: EFIHandle)
+    -> EFIResult<ProtocolGuard<'a, EFILoadedImageProtocol<'static>>>
+{
+    table.boot_services.open_protocol::<EFILoadedImageProtocol>(
+        image_handle, image_handle, 0,
+        OpenProtocolAttributes::BY_HANDLE_PROTOCOL)
+}
+
+fn print_boot_device(table: &SystemTable, image_handle: EFIHandle)
+    -> EFIResult<()>
+{
+    let loaded_image = loaded_image(table, image_handle)?;
+
+    let device_path = table.boot_services
+        .handle_protocol::<EFIDevicePathProtocol>(
+            loaded_image.device_handle)?;
+
+    println!("Boot Device: {}", device_path);
+    println!("Boot Image: {}", loaded_image.file_path);
+
+    // Print the partition so it's easy to see which disk we booted from
+    let partition = device_path.nodes().find_map(|node| match node.kind() {
+        DeviceNode::HardDrive { partition_number, signature, .. } =>
+            Some((partition_number, signature)),
+        _ => None,
+    });
+
+    match partition {
+        Some((number, PartitionSignature::Gpt(guid))) =>
+            println!("Boot Partition: {} (GPT {})", number, guid),
+        Some((number, PartitionSignature::Mbr(signature))) =>
+            println!("Boot Partition: {} (MBR {:#010x})", number, signature),
+        Some((number, PartitionSignature::None)) =>
+            println!("Boot Partition: {}", number),
+        None => println!("Boot Partition: None"),
+    }
+
+    Ok(())
+}
+
+/// A boot directory and the device the directory is on
+struct BootDirectory {
+    device: EFIHandle,
+    directory: Directory<'static>,
+}
 
-    let loaded_image =
-        unsafe { &*(loaded_image_ptr as *const EFILoadedImageProtocol) };
+impl core::ops::Deref for BootDirectory {
+    type Target = Directory<'static>;
 
-    loaded_image
+    fn deref(&self) -> &Directory<'static> {
+        &self.directory
+    }
 }
 
-fn simple_filesystem<'a>(table: &SystemTable,
-                         loaded_image: &'a EFILoadedImageProtocol)
-    -> &'a EFISimpleFilesystem
+fn boot_directories(image_handle: EFIHandle, dirname: &str)
+    -> EFIResult<Vec<BootDirectory>>
 {
-    let simple_filesystem_ptr =
-        table.boot_services.handle_protocol(loaded_image.device_handle,
-                                            &SIMPLE_FILESYSTEM_GUID);
+    let table = unsafe { TABLE.unwrap() };
 
-    let simple_filesystem =
-        unsafe { &*(simple_filesystem_ptr as *const EFISimpleFilesystem) };
+    // We only need the loaded image to find the device we booted from
+    // so the protocol is closed when the guard is dropped
+    let boot_device = loaded_image(&table, image_handle)?.device_handle;
+
+    // Search the device we booted from first and then the rest
+    let mut handles =
+        table.boot_services.find_handles::<EFISimpleFilesystem>()?;
+    handles.retain(|&handle| handle != boot_device);
+    handles.insert(0, boot_device);
+
+    let mut directories = Vec::new();
+    for handle in handles {
+        let directory = table.boot_services
+            .handle_protocol::<EFISimpleFilesystem>(handle)
+            .and_then(|filesystem| filesystem.open_volume())
+            .and_then(|volume| volume.open_directory(dirname));
+
+        // Skip the volumes without the boot directory
+        if let Ok(directory) = directory {
+            directories.push(BootDirectory {
+                device: handle,
+                directory,
+            });
+        }
+    }
 
-    simple_filesystem
+    Ok(directories)
 }
 
-fn get_boot_directory(handle: EFIHandle, dirname: &str)
-    -> &EFIFileHandle
+fn load_file(directory: &Directory, filename: &str) -> EFIResult<Vec<u8>> {
+    let file = directory.open_file(filename, FileMode::READ)?;
+
+    file.read_to_end()
+}
+
+fn write_file(directory: &Directory, filename: &str, data: &[u8])
+    -> EFIResult<()>
 {
-    let table = unsafe { TABLE.unwrap() };
+    let file = directory.create_file(filename)?;
+
+    // Remove the old content of the file before writing the new content
+    file.set_size(0)?;
+    file.write_all(data)?;
+
+    // The close flushes the file so report the error if it fails
+    file.close()
+}
+
+/// The extension of the files `find_kernels` looks for
+const KERNEL_EXTENSION: &str = ".kern";
+
+/// Find the kernels in the directory, the kernels are sorted by name
+/// so the order doesn't depend on the filesystem
+fn find_kernels(directory: &Directory) -> Vec<String> {
+    let mut kernels = Vec::new();
+
+    let entries = match directory.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return kernels,
+    };
+
+    // Skip the entries we fail to read
+    for entry in entries.flatten() {
+        if !entry.is_directory() &&
+            entry.name.to_lowercase().ends_with(KERNEL_EXTENSION)
+        {
+            kernels.push(entry.name);
+        }
+    }
+
+    kernels.sort();
+
+    kernels
+}
+
+fn load_file_from_any(directories: &[BootDirectory], filename: &str)
+    -> EFIResult<(usize, Vec<u8>)>
+{
+    let mut result =
+        Err(EFIError::new(EFIStatus::NotFound, "load_file_from_any"));
+
+    // Return the first file we find and the index of the directory
+    for (index, directory) in directories.iter().enumerate() {
+        result = load_file(directory, filename)
+            .map(|buffer| (index, buffer));
+
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result
+}
+
+/// Get the command line we were started with, the command line is empty
+/// if the one who started us didn't give us one
+fn load_options(table: &SystemTable, image_handle: EFIHandle)
+    -> EFIResult<String>
+{
+    let loaded_image = loaded_image(table, image_handle)?;
+
+    Ok(loaded_image.load_options_string().unwrap_or_default())
+}
+
+/// Build the path the firmware should give to a image we load from the
+/// boot directory on the device
+fn boot_file_path(table: &SystemTable, device: EFIHandle, filename: &str)
+    -> EFIResult<DevicePathBuf>
+{
+    let device_path = table.boot_services
+        .handle_protocol::<EFIDevicePathProtocol>(device)?;
+
+    let mut result = DevicePathBuf::from_path(device_path);
+    let path = Path::new(BOOT_DIRECTORY).join(filename);
+    result.push_file_path(&path.to_string())?;
+
+    Ok(result)
+}
+
+/// Load a EFI application from the boot directories and run it until it
+/// exits
+fn chainload(table: &SystemTable, image_handle: EFIHandle,
+             directories: &[BootDirectory], filename: &str)
+    -> EFIResult<ImageExit>
+{
+    let (index, buffer) = load_file_from_any(directories, filename)?;
+
+    // NOTE(patrik): The path is only so the application can find the
+    // files next to it, the application can still run without it. The
+    // path is on the device we found the file on
+    let device = directories[index].device;
+    let file_path = boot_file_path(table, device, filename).ok();
+
+    let handle = table.boot_services.load_image(image_handle,
+        LoadImageSource::FromBuffer {
+            buffer: &buffer,
+            file_path: file_path.as_ref().map(|p| p.as_device_path()),
+        })?;
+
+    Ok(table.boot_services.start_image(handle))
+}
+
+fn graphics_output(table: &SystemTable<'static>)
+    -> EFIResult<&'static EFIGraphicsOutputProtocol<'static>>
+{
+    let handles = table.boot_services
+        .find_handles::<EFIGraphicsOutputProtocol>()?;
+
+    let mut result = None;
+    for handle in handles {
+        // Skip the outputs we can't open so one bad handle doesn't hide
+        // the other outputs
+        let gop = match table.boot_services
+            .handle_protocol::<EFIGraphicsOutputProtocol>(handle)
+        {
+            Ok(gop) => gop,
+            Err(_) => continue,
+        };
+
+        let info = gop.mode.info;
+        println!("Graphics Output {:#x}: {}x{}",
+                 handle, info.width, info.height);
+
+        // Pick the output with the highest resolution
+        let pixels = info.width as u64 * info.height as u64;
+        match result {
+            Some((best, _)) if best >= pixels => {}
+            _ => result = Some((pixels, gop)),
+        }
+    }
+
+    match result {
+        Some((_, gop)) => Ok(gop),
+        None => table.boot_services.locate_protocol(),
+    }
+}
 
-    let loaded_image = loaded_image(&table, handle);
-    let simple_filesystem = simple_filesystem(&table, &loaded_image);
-    let volume = simple_filesystem.open_volume();
+/// The resolution the bootloader should set before entering the kernel
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Resolution {
+    /// Keep the mode the firmware picked
+    Firmware,
+    /// Pick the mode with the most pixels
+    Max,
+    /// Pick the mode with the width and height
+    Size(u32, u32),
+}
+
+impl Resolution {
+    /// Parse a resolution from the options i.e 'max' or '1920x1080'
+    fn parse(value: &str) -> Option<Self> {
+        if value == "max" {
+            return Some(Resolution::Max);
+        }
 
-    let handle = volume.open(dirname, 0x0000000000000001, 0x0000000000000001);
+        let mut split = value.splitn(2, 'x');
+        let width = split.next()?.parse().ok()?;
+        let height = split.next()?.parse().ok()?;
+
+        Some(Resolution::Size(width, height))
+    }
+}
+
+fn set_resolution(table: &SystemTable,
+                  gop: &EFIGraphicsOutputProtocol,
+                  resolution: Resolution)
+    -> EFIResult<()>
+{
+    let mut result = None;
+    for (mode_number, info) in gop.modes(table.boot_services) {
+        // Skip the modes the firmware fails to query
+        let info = match info {
+            Ok(info) => info,
+            Err(_) => continue,
+        };
+
+        println!("Graphics Mode {}: {}x{}",
+                 mode_number, info.width, info.height);
+
+        let pixels = info.width as u64 * info.height as u64;
+        match resolution {
+            Resolution::Firmware => {}
+
+            Resolution::Max => match result {
+                Some((_, best)) if best >= pixels => {}
+                _ => result = Some((mode_number, pixels)),
+            },
+
+            Resolution::Size(width, height) => {
+                if info.width == width && info.height == height {
+                    result = Some((mode_number, pixels));
+                }
+            }
+        }
+    }
+
+    match result {
+        Some((mode_number, _)) if mode_number != gop.mode.current_mode() =>
+            gop.set_mode(mode_number),
+        Some(_) => Ok(()),
+        None if resolution == Resolution::Firmware => Ok(()),
+        None => Err(EFIError::new(EFIStatus::Unsupported, "set_resolution")),
+    }
+}
 
-    handle
+/// Convert the pixel format of the mode to the format the kernel uses
+fn pixel_format(info: &EFIGraphicsOutputInfo) -> PixelFormat {
+    match info.pixel_format {
+        EFIGraphicsPixelFormat::PixelRedGreenBlueReserved8BitPerColor =>
+            PixelFormat::from_layout(PixelLayout::RGB),
+        EFIGraphicsPixelFormat::PixelBlueGreenRedReserved8BitPerColor =>
+            PixelFormat::from_layout(PixelLayout::BGR),
+        EFIGraphicsPixelFormat::PixelBitMask => {
+            let masks = &info.pixel_infomation;
+            PixelFormat::from_masks(masks.red_mask, masks.green_mask,
+                                    masks.blue_mask, masks.reserved_mask)
+        }
+        _ => PixelFormat::from_layout(PixelLayout::BLT_ONLY),
+    }
 }
 
-fn load_file(directory: &EFIFileHandle, filename: &str)
-    -> Option<Vec<u8>>
+/// Height of the progress bar in pixels
+const PROGRESS_BAR_HEIGHT: usize = 8;
+
+/// Draw a progress bar at the bottom of the screen, `progress` is in
+/// percent. The bar uses blt so it works without a linear framebuffer
+fn draw_progress(table: &SystemTable, gop: &EFIGraphicsOutputProtocol,
+                 progress: usize)
+    -> EFIResult<()>
 {
-    let file_handle = directory.open(filename,
-                                  0x0000000000000001, 0x0000000000000001);
+    let width = gop.mode.info.width as usize;
+    let height = gop.mode.info.height as usize;
+    if width == 0 || height < PROGRESS_BAR_HEIGHT {
+        return Ok(());
+    }
 
-    let file_content = file_handle.read_to_buffer();
+    // NOTE(patrik): We don't know the size of the firmware font so the
+    // rows are assumed to cover the whole screen, that is the highest the
+    // rows can be. The bar isn't drawn once the text reaches the rows
+    // the bar would cover
+    let console = table.console_out;
+    let (_, rows) = console.query_mode(console.mode().mode as usize)?;
+    let row_height = height / rows.max(1);
+    let cursor_row = console.mode().cursor_row.max(0) as usize;
+
+    let y = height - PROGRESS_BAR_HEIGHT;
+    if (cursor_row + 1) * row_height > y {
+        return Ok(());
+    }
+
+    let done = width * progress.min(100) / 100;
+
+    // Draw the background of the bar and then the part that is done
+    gop.blt(BltOperation::VideoFill {
+        color: BltPixel::new(0x30, 0x30, 0x30),
+        dest: (0, y),
+        size: (width, PROGRESS_BAR_HEIGHT),
+    })?;
 
-    Some(file_content)
+    gop.blt(BltOperation::VideoFill {
+        color: BltPixel::new(0xd9, 0xa4, 0x41),
+        dest: (0, y),
+        size: (done, PROGRESS_BAR_HEIGHT),
+    })
+}
+
+fn wait_for_key(table: &SystemTable) -> EFIInputKey {
+    let events = [table.console_in.wait_for_key()];
+
+    loop {
+        if let Err(err) = table.boot_services.wait_for_any(&events) {
+            panic!("Failed to wait for key: {}", err);
+        }
+
+        // The event can be signaled without a key so try again if
+        // there is no key
+        match table.console_in.read_key_stroke() {
+            Ok(Some(key)) => return key,
+            Ok(None) => {}
+            Err(err) => panic!("Failed to read key: {}", err),
+        }
+    }
+}
+
+fn boot_countdown(table: &SystemTable, timeout: u64) -> EFIResult<bool> {
+    let timer = table.boot_services.create_timer()?;
+    table.boot_services.set_timer(timer.event(), EFITimerDelay::Periodic,
+                                  TIMER_TICKS_PER_SECOND)?;
+
+    let events = [table.console_in.wait_for_key(), timer.event()];
+
+    // Count down the seconds until the timeout or until a key is pressed
+    for remaining in (1..=timeout).rev() {
+        print!("\rBooting in {} seconds, press any key to interrupt ",
+               remaining);
+
+        if table.boot_services.wait_for_any(&events)? == 0 {
+            // Remove the key so it's not used by the next prompt
+            let _ = table.console_in.read_key_stroke();
+            println!();
+
+            return Ok(true);
+        }
+    }
+
+    println!();
+
+    Ok(false)
+}
+
+/// Let the operator pick what to boot with the number keys, `None` is
+/// the kernel from the options. Only the first 9 entries can be picked
+fn select_entry(table: &SystemTable, options: &BootloaderOptions)
+    -> Option<usize>
+{
+    println!("0: {}", options.kernel_filename);
+    for (index, entry) in options.entries.iter().take(9).enumerate() {
+        println!("{}: {}", index + 1, entry.name);
+    }
+
+    println!("Select what to boot");
+
+    loop {
+        let key = wait_for_key(table);
+
+        let digit = key.char().and_then(|c| c.to_digit(10));
+        match digit {
+            Some(0) => return None,
+            Some(digit) if digit as usize <= options.entries.len() =>
+                return Some(digit as usize - 1),
+            _ => {}
+        }
+    }
+}
+
+/// Vendor GUID for the variables the bootloader stores
+const POTATO_VARIABLE_GUID: EFIGuid =
+    EFIGuid::new(0x3d6b1f0e, 0x8c1a, 0x4f6e,
+                 [0x9b, 0x52, 0x5e, 0x0a, 0x7c, 0x2d, 0x4b, 0x19]);
+
+/// Name of the variable with the filename of the last booted kernel
+const LAST_BOOT_VARIABLE: &str = "PotatoLastBoot";
+
+fn print_boot_variables(variables: &VariableStore) {
+    if let Ok(current) = variables.boot_current() {
+        match variables.boot_option(current) {
+            Ok(option) => println!("Firmware Boot Entry: Boot{:04X} '{}'",
+                                   current, option.description),
+            Err(_) => println!("Firmware Boot Entry: Boot{:04X}", current),
+        }
+    }
+
+    if let Ok(order) = variables.boot_order() {
+        println!("Firmware Boot Order: {:04X?}", order);
+    }
+
+    if let Ok(secure_boot) = variables.secure_boot() {
+        println!("Secure Boot: {}", secure_boot);
+    }
+
+    if let Ok(last_boot) =
+        variables.read(LAST_BOOT_VARIABLE, &POTATO_VARIABLE_GUID)
+    {
+        println!("Last Boot: {}", String::from_utf8_lossy(&last_boot.data));
+    }
+}
+
+/// The directory the bootloader looks for the options in, the paths in
+/// the options are relative to the directory with the options
+const BOOT_DIRECTORY: &str = "/EFI/boot";
+
+/// The code reported by the watchdog when it resets the system, codes
+/// below 0x10000 are reserved for the firmware
+const WATCHDOG_CODE: u64 = 0x10000;
+
+/// What a boot entry boots
+#[derive(Clone, Debug)]
+enum EntryKind {
+    /// Load the kernel from the file
+    Kernel(String),
+    /// Start the EFI application in the file i.e the UEFI shell
+    Chainload(String),
+}
+
+/// A named entry from a '[entry NAME]' section in the options
+#[derive(Clone, Debug)]
+struct BootEntry {
+    name: String,
+    kind: EntryKind,
 }
 
 #[derive(Debug)]
 struct BootloaderOptions {
     kernel_font: String,
     kernel_filename: String,
+    timeout: u64,
+    watchdog: usize,
+    resolution: Resolution,
+    boot_log: Option<String>,
+    /// Boot the kernels `find_kernels` finds if the configured kernels
+    /// fails to load
+    scan_kernels: bool,
+    entries: Vec<BootEntry>,
+    default_entry: Option<String>,
+    /// The directory with the options, relative to the boot directory,
+    /// the paths in the options are relative to it
+    directory: Path,
+}
+
+impl BootloaderOptions {
+    /// Resolve a path in the options
+    fn path(&self, value: &str) -> String {
+        self.directory.join(value).to_string()
+    }
+
+    /// Get the entry with the name, the entry is created if it doesn't
+    /// exist
+    fn entry_mut(&mut self, name: &str) -> &mut BootEntry {
+        let index = match self.entries.iter().position(|e| e.name == name) {
+            Some(index) => index,
+            None => {
+                self.entries.push(BootEntry {
+                    name: name.to_string(),
+                    kind: EntryKind::Kernel(self.kernel_filename.clone()),
+                });
+
+                self.entries.len() - 1
+            }
+        };
+
+        &mut self.entries[index]
+    }
+
+    /// Set a option from the bootloader section
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "load_font" => self.kernel_font = self.path(value),
+            "kernel" => self.kernel_filename = self.path(value),
+            "scan_kernels" =>
+                self.scan_kernels = value.parse().map_err(|_| {
+                    format!("Invalid scan_kernels: '{}'", value)
+                })?,
+            "timeout" =>
+                self.timeout = value.parse().map_err(|_| {
+                    format!("Invalid timeout: '{}'", value)
+                })?,
+            "watchdog" =>
+                self.watchdog = value.parse().map_err(|_| {
+                    format!("Invalid watchdog: '{}'", value)
+                })?,
+            "boot_log" => self.boot_log = Some(self.path(value)),
+            "resolution" =>
+                self.resolution = Resolution::parse(value).ok_or_else(|| {
+                    format!("Invalid resolution: '{}'", value)
+                })?,
+            "default" =>
+                self.default_entry = Some(value.to_string()),
+            _ => return Err(format!("Unknown option: '{}'", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Set a option from a '[entry NAME]' section
+    fn set_entry(&mut self, name: &str, key: &str, value: &str)
+        -> Result<(), String>
+    {
+        let path = self.path(value);
+        let entry = self.entry_mut(name);
+
+        match key {
+            "kernel" => entry.kind = EntryKind::Kernel(path),
+            "chainload" => entry.kind = EntryKind::Chainload(path),
+            _ => return Err(format!("Unknown entry option: '{}'", key)),
+        }
+
+        Ok(())
+    }
 }
 
 impl Default for BootloaderOptions {
@@ -187,190 +755,685 @@ impl Default for BootloaderOptions {
         Self {
             kernel_font: "font.fnt".to_string(),
             kernel_filename: "kernel.kern".to_string(),
+            timeout: 0,
+            watchdog: 0,
+            resolution: Resolution::Firmware,
+            boot_log: None,
+            scan_kernels: false,
+            entries: Vec::new(),
+            default_entry: None,
+            directory: Path::default(),
+        }
+    }
+}
+
+/// Load the segments of the kernel to the physical addresses the kernel
+/// was linked for and return the entry point
+fn load_kernel(table: &SystemTable, binary: &[u8]) -> EFIResult<u64> {
+    let invalid = || EFIError::new(EFIStatus::LoadError, "load_kernel");
+
+    let elf = match Elf::from_bytes(binary) {
+        Ok(Elf::Elf64(elf)) => elf,
+        Ok(_) => return Err(EFIError::new(EFIStatus::Unsupported,
+                                          "load_kernel")),
+        Err(_) => return Err(invalid()),
+    };
+
+    // Check the segments and find the pages all the segments are in,
+    // the segments doesn't need to be page aligned so two segments can
+    // share a page
+    let mut segments = Vec::new();
+    let mut first_page = u64::MAX;
+    let mut last_page = 0;
+    for p in elf.program_header_iter() {
+        if p.ph.ph_type() != ProgramType::LOAD || p.ph.memsz() == 0 {
+            continue;
         }
+
+        let address = p.ph.paddr();
+        let start = p.ph.offset() as usize;
+        let data = (p.ph.filesz() as usize).checked_add(start)
+            .and_then(|end| binary.get(start..end))
+            .filter(|data| data.len() as u64 <= p.ph.memsz())
+            .ok_or_else(invalid)?;
+        let end = address.checked_add(p.ph.memsz()).ok_or_else(invalid)?;
+
+        first_page = first_page.min(address & !(PAGE_SIZE - 1));
+        last_page = last_page.max(page_align_up(end));
+
+        segments.push((address, data, p.ph.memsz()));
+    }
+
+    if segments.is_empty() {
+        return Err(invalid());
+    }
+
+    let pages = (last_page - first_page) / PAGE_SIZE;
+    if let Err(err) = table.boot_services
+        .allocate_at(first_page, EFIMemoryType::OsLoaderKernel, pages)
+    {
+        println!("Failed to allocate the kernel at {:#x}-{:#x}",
+                 first_page, last_page);
+        return Err(err);
     }
+
+    for (address, data, size) in segments {
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), address as *mut u8,
+                                           data.len());
+
+            // The memory after the data is the bss so it needs to be
+            // cleared
+            let bss = (address + data.len() as u64) as *mut u8;
+            core::ptr::write_bytes(bss, 0, (size - data.len() as u64) as usize);
+        }
+    }
+
+    Ok(elf.header().entry_point())
+}
+
+/// The number of extra descriptors we make room for in the final memory
+/// map, the allocations before exit_boot_services can split regions
+const MEMORY_MAP_SLACK: usize = 16;
+
+fn print_memory_summary(memory_map: &MemoryMap) {
+    let classes = [
+        MemoryClass::Usable,
+        MemoryClass::Reclaimable,
+        MemoryClass::Loader,
+        MemoryClass::AcpiReclaimable,
+        MemoryClass::AcpiNvs,
+        MemoryClass::Mmio,
+        MemoryClass::Reserved,
+    ];
+
+    for &class in classes.iter() {
+        println!("{:?} Memory: {} KiB", class,
+                 memory_map.total_size(class) / 1024);
+    }
+
+    println!("Highest Usable Address: {:#x}",
+             memory_map.highest_usable_address().unwrap_or(0));
+}
+
+/// The kind of memory the kernel sees for a firmware region
+fn region_kind(descriptor: &MemoryDescriptor) -> MemoryRegionKind {
+    match descriptor.memory_type() {
+        Some(EFIMemoryType::UnusableMemory) => return MemoryRegionKind::BAD,
+        Some(EFIMemoryType::OsLoaderKernel) =>
+            return MemoryRegionKind::KERNEL,
+        Some(EFIMemoryType::OsLoaderBootInfo) =>
+            return MemoryRegionKind::BOOTLOADER_RECLAIMABLE,
+        _ => {}
+    }
+
+    match descriptor.class() {
+        // The boot services and the bootloader are gone when the kernel
+        // runs, what the kernel needs is in the types above
+        MemoryClass::Usable | MemoryClass::Reclaimable |
+        MemoryClass::Loader => MemoryRegionKind::USABLE,
+        MemoryClass::AcpiReclaimable => MemoryRegionKind::ACPI_RECLAIMABLE,
+        MemoryClass::AcpiNvs => MemoryRegionKind::ACPI_NVS,
+        MemoryClass::Mmio | MemoryClass::Reserved =>
+            MemoryRegionKind::RESERVED,
+    }
+}
+
+/// The size of the stack the kernel starts on
+const KERNEL_STACK_SIZE: u64 = 64 * 1024;
+
+/// Switch to the kernel stack and call the kernel, the entry point is a
+/// `extern "sysv64" fn(boot_info: &'static mut BootInfo) -> !`
+///
+/// # Safety
+/// The entry point needs to be the entry point of the loaded kernel and
+/// the stack can't be used by anything else
+unsafe fn enter_kernel(entry_point: u64, boot_info: &'static mut BootInfo,
+                       stack: PhysRange) -> !
+{
+    // NOTE(patrik): The top of the stack is page aligned so the stack is
+    // aligned the way the sysv64 ABI wants it after the call
+    asm!("mov rsp, {stack}",
+         "xor rbp, rbp",
+         "call {entry}",
+         stack = in(reg) stack.end(),
+         entry = in(reg) entry_point,
+         in("rdi") boot_info as *mut BootInfo,
+         options(noreturn));
+}
+
+/// Set a kernel option, a option that is already set is replaced so the
+/// options set last takes precedence
+fn set_kernel_option<'a>(options: &mut Vec<(&'a str, &'a str)>,
+                         key: &'a str, value: &'a str)
+{
+    match options.iter_mut().find(|(k, _)| *k == key) {
+        Some(option) => option.1 = value,
+        None => options.push((key, value)),
+    }
+}
+
+/// Build the command line for the kernel, the values are quoted if they
+/// aren't quoted already
+fn kernel_command_line(options: &[(&str, &str)]) -> String {
+    let mut result = String::new();
+
+    for (key, value) in options {
+        result.push_str(key);
+        result.push('=');
+
+        if value.starts_with('\"') {
+            result.push_str(value);
+        } else {
+            result.push('\"');
+            result.push_str(value);
+            result.push('\"');
+        }
+
+        result.push(' ');
+    }
+
+    result
 }
 
 #[no_mangle]
 fn efi_main(image_handle: EFIHandle, 
             table: &SystemTable<'static>) -> u64
 {
-    table.console_out.clear_screen();
+    let _ = table.console_out.clear_screen();
 
     unsafe {
-        WRITER = Some(TextWriter::new(table.console_out));
+        // NOTE(patrik): The writer allocates the log so the table needs
+        // to be set first
         TABLE = Some(*table);
+        RUNTIME_SERVICES = Some(table.runtime_services);
+        WRITER = Some(TextWriter::new(table.console_out));
     }
 
     println!("Welcome to the potato bootloader v0.1");
 
-    let directory = get_boot_directory(image_handle, "EFI\\boot\\");
+    let variables = VariableStore::new(table.runtime_services);
+    print_boot_variables(&variables);
+
+    if let Err(err) = print_boot_device(table, image_handle) {
+        println!("Failed to get the boot device: {}", err);
+    }
+
+    // Disable the watchdog the firmware armed so we don't get reset
+    // while loading, the options can enable it again
+    if let Err(err) =
+        table.boot_services.set_watchdog_timer(0, WATCHDOG_CODE)
+    {
+        println!("Failed to disable the watchdog: {}", err);
+    }
+
+    let mut directories = match boot_directories(image_handle, BOOT_DIRECTORY) {
+        Ok(directories) => directories,
+        Err(err) => panic!("Failed to open the boot directory: {}", err),
+    };
+
+    // The options on the command line takes precedence over the options
+    // in the file so they are applied last
+    let command_line = load_options(table, image_handle)
+        .unwrap_or_else(|err| {
+            println!("Failed to get the command line: {}", err);
+            String::new()
+        });
+    println!("Command Line: {}", command_line);
+
+    // The command line can tell us where the options are
+    let mut config = None;
+    OptionParser::new(&command_line).command_line(|category, key, value| {
+        if category == Category::Bootloader && key == "config" {
+            config = Some(Path::new(value).to_string());
+        }
+
+        Some(())
+    }).unwrap();
 
-    let filename = "options.txt";
+    let filename = config.as_deref().unwrap_or("options.txt");
     println!("Loading: {}", filename);
 
-    let buffer = load_file(directory, filename).unwrap();
-    let option_str = core::str::from_utf8(&buffer[..]).unwrap();
+    // If the options can't be loaded we continue with the default options
+    let buffer = match load_file_from_any(&directories, filename) {
+        Ok((index, buffer)) => {
+            // Search for the kernel in the same directory as the options
+            let directory = directories.remove(index);
+            directories.insert(0, directory);
+
+            buffer
+        }
+        Err(err) => {
+            println!("Failed to load '{}': {}", filename, err);
+            println!("Using the default options");
+            Vec::new()
+        }
+    };
+
+    // Options we can't read are handled like missing options
+    let option_str = core::str::from_utf8(&buffer[..]).unwrap_or_else(|err| {
+        println!("The options in '{}' isn't UTF-8: {}", filename, err);
+        println!("Using the default options");
+        ""
+    });
     println!("Text:\n{}", option_str);
 
     let mut bootloader_options = BootloaderOptions::default();
+    let mut kernel_options = Vec::new();
 
-    let mut buffer = [0u8; 2048];
-    let mut index = 0;
+    // The paths in the options are relative to the options
+    if let Some(directory) = Path::new(filename).parent() {
+        bootloader_options.directory = directory;
+    }
 
     let option_parser = OptionParser::new(option_str);
     option_parser.options(|category, key, value| {
         println!("'{:?}': {:?} = {:?}", category, key, value);
 
-        if category == Category::Bootloader {
-            match key {
-                "load_font" =>
-                    bootloader_options.kernel_font = value.to_string(),
-                "kernel" =>
-                    bootloader_options.kernel_filename = value.to_string(),
-                _ => {
-                    panic!("Unknown option: '{}'", key);
+        let result = match category {
+            Category::Bootloader => bootloader_options.set(key, value),
+            Category::Entry(name) =>
+                bootloader_options.set_entry(name, key, value),
+            Category::Kernel => {
+                set_kernel_option(&mut kernel_options, key, value);
+                Ok(())
+            }
+        };
+
+        if let Err(err) = result {
+            panic!("{}", err);
+        }
+
+        Some(())
+    }).unwrap();
+
+    let option_parser = OptionParser::new(&command_line);
+    option_parser.command_line(|category, key, value| {
+        println!("Command Line '{:?}': {:?} = {:?}", category, key, value);
+
+        let result = match category {
+            // The config was used to find the options
+            Category::Bootloader if key == "config" => Ok(()),
+            // Only pick a entry we know about so a typo boots the default
+            // entry from the options
+            Category::Bootloader if key == "entry" || key == "default" => {
+                let known = bootloader_options.entries.iter()
+                    .any(|entry| entry.name == value);
+
+                if known {
+                    bootloader_options.set("default", value)
+                } else {
+                    Err(format!("Unknown entry: '{}'", value))
                 }
             }
-        } else if category == Category::Kernel {
-            buffer[index..index+key.len()]
-                .clone_from_slice(&key.as_bytes()[..]);
-            index += key.len();
-
-            buffer[index] = b'=';
-            index += 1;
-
-            if value.as_bytes()[0] == b'\"' {
-                buffer[index..index+value.len()]
-                    .clone_from_slice(&value.as_bytes()[..]);
-                index += value.len();
-            } else {
-                buffer[index] = b'\"';
-                index += 1;
-
-                buffer[index..index+value.len()]
-                    .clone_from_slice(&value.as_bytes()[..]);
-                index += value.len();
-
-                buffer[index] = b'\"';
-                index += 1;
+            Category::Bootloader => bootloader_options.set(key, value),
+            Category::Entry(name) =>
+                bootloader_options.set_entry(name, key, value),
+            Category::Kernel => {
+                set_kernel_option(&mut kernel_options, key, value);
+                Ok(())
             }
+        };
 
-            buffer[index] = b' ';
-            index += 1;
-        } else {
-            panic!("Unknown category: {:?}", category);
+        // NOTE(patrik): The command line is hard to fix from the firmware
+        // so a typo shouldn't stop the boot
+        if let Err(err) = result {
+            println!("Ignoring the command line option: {}", err);
         }
 
         Some(())
     }).unwrap();
 
     println!("Bootloader Options: {:#?}", bootloader_options);
-    println!("Kernel Options: {}",
-             core::str::from_utf8(&buffer[0..index]).unwrap());
 
-    let ptr =
-        table.boot_services.locate_protocol(&GRAPHICS_OUTPUT_PROTOCOL_GUID);
-    let gop = ptr as *const EFIGraphicsOutputProtocol;
-    let gop = unsafe { &*gop };
+    // Nothing to keep the output for if the options doesn't want a log
+    if bootloader_options.boot_log.is_none() {
+        unsafe {
+            if let Some(writer) = WRITER.as_mut() {
+                writer.stop_log();
+            }
+        }
+    }
+    println!("Kernel Options: {}", kernel_command_line(&kernel_options));
+
+    // Arm the watchdog again if the options wants it, 0 keeps it disabled
+    if bootloader_options.watchdog > 0 {
+        table.boot_services
+            .set_watchdog_timer(bootloader_options.watchdog, WATCHDOG_CODE)
+            .expect("Failed to set the watchdog");
+    }
+
+    // A key pressed while the options was loading or before the
+    // timeout means that the operator wants to interrupt the boot
+    let interrupted = match table.console_in.read_key_stroke() {
+        Ok(Some(_)) => true,
+        _ => boot_countdown(table, bootloader_options.timeout)
+            .unwrap_or_else(|err| {
+                println!("Failed to wait for the timeout: {}", err);
+                false
+            }),
+    };
+
+    let mut selected = bootloader_options.default_entry.as_ref()
+        .map(|name| {
+            bootloader_options.entries.iter()
+                .position(|entry| &entry.name == name)
+                .unwrap_or_else(|| panic!("Unknown default entry: '{}'", name))
+        });
+
+    if interrupted {
+        println!("Boot interrupted");
+        selected = select_entry(table, &bootloader_options);
+    }
+
+    // Run the applications the operator picks until a kernel is picked
+    let entry_kernel = loop {
+        let entry = selected.map(|index| &bootloader_options.entries[index]);
+
+        match entry.map(|entry| &entry.kind) {
+            Some(EntryKind::Chainload(filename)) => {
+                println!("Chainloading: {}", filename);
+
+                // The application can run for as long as it wants so
+                // don't let the watchdog reset the machine
+                let _ = table.boot_services
+                    .set_watchdog_timer(0, WATCHDOG_CODE);
+
+                match chainload(table, image_handle, &directories, filename) {
+                    Ok(exit) => {
+                        let data = exit.exit_data.as_deref().unwrap_or("");
+                        match exit.status() {
+                            Some(status) =>
+                                println!("'{}' exited with {:?}: {}",
+                                         filename, status, data),
+                            None =>
+                                println!("'{}' exited with {:#x}: {}",
+                                         filename, exit.raw_status, data),
+                        }
+                    }
+                    Err(err) => println!("Failed to chainload '{}': {}",
+                                         filename, err),
+                }
+
+                if bootloader_options.watchdog > 0 {
+                    table.boot_services
+                        .set_watchdog_timer(bootloader_options.watchdog,
+                                            WATCHDOG_CODE)
+                        .expect("Failed to set the watchdog");
+                }
+
+                selected = select_entry(table, &bootloader_options);
+            }
+            Some(EntryKind::Kernel(filename)) => break Some(filename.clone()),
+            None => break None,
+        }
+    };
+
+    let gop = graphics_output(table)
+        .expect("Failed to locate the graphics output protocol");
+
+    // Keep the mode the firmware picked if the resolution can't be set
+    if let Err(err) =
+        set_resolution(table, gop, bootloader_options.resolution)
+    {
+        println!("Failed to set the resolution {:?}: {}",
+                 bootloader_options.resolution, err);
+    }
 
     println!("Framebuffer Size: {}", gop.mode.framebuffer_size);
     println!("Framebuffer Info: {:#?}", gop.mode.info);
 
-    let filename = bootloader_options.kernel_filename;
-    let kernel_binary = load_file(directory, &filename).unwrap();
+    // NOTE(patrik): The progress bar is only decoration so the errors
+    // are ignored
+    let _ = draw_progress(table, gop, 0);
+
+    // Try the kernel from the entry, then the kernel from the options
+    // and then the default kernel
+    let mut candidates: Vec<String> = Vec::new();
+    let fallbacks = [
+        bootloader_options.kernel_filename.clone(),
+        BootloaderOptions::default().kernel_filename,
+    ];
+    for filename in entry_kernel.into_iter().chain(fallbacks.iter().cloned()) {
+        if !candidates.contains(&filename) {
+            candidates.push(filename);
+        }
+    }
 
-    let elf = Elf::from_bytes(&kernel_binary).unwrap();
-    let entry_point = if let Elf::Elf64(e) = elf {
-        for p in e.program_header_iter() {
-            let pages = (p.ph.memsz() + 0x1000 - 1) / 0x1000;
+    let mut loaded = None;
+    for filename in candidates.iter() {
+        println!("Loading kernel: {}", filename);
 
-            if pages <= 0 {
-                continue;
+        match load_file_from_any(&directories, filename) {
+            Ok((_, binary)) => {
+                loaded = Some((filename.clone(), binary));
+                break;
             }
+            Err(err) => println!("Failed to load kernel '{}': {}",
+                                 filename, err),
+        }
+    }
 
-            let mut address = p.ph.paddr();
-            table.boot_services.allocate_pages(EFIAllocateType::AllocateAddress,
-                                               EFIMemoryType::LoaderData,
-                                               pages, &mut address);
-
-            let start = p.ph.offset() as usize;
-            let end =
-                p.ph.offset().checked_add(p.ph.filesz()).unwrap() as usize;
-            let slice = &kernel_binary[start..end];
+    // NOTE(patrik): The scan is only done if the options asks for it and
+    // only in the directory with the options, we don't want to boot a
+    // kernel from a random USB stick
+    if loaded.is_none() && bootloader_options.scan_kernels {
+        if let Some(directory) = directories.first() {
+            for filename in find_kernels(directory) {
+                if candidates.contains(&filename) {
+                    continue;
+                }
 
-            let data = unsafe {
-                core::slice::from_raw_parts_mut(address as *mut u8,
-                                                (pages * 4096) as usize)
-            };
+                println!("Loading kernel found by the scan: {}", filename);
 
-            data[..slice.len()].copy_from_slice(&slice);
+                match load_file(directory, &filename) {
+                    Ok(binary) => {
+                        loaded = Some((filename, binary));
+                        break;
+                    }
+                    Err(err) => println!("Failed to load kernel '{}': {}",
+                                         filename, err),
+                }
+            }
         }
+    }
+
+    let (filename, kernel_binary) = loaded.expect("No kernel to load");
+
+    let _ = draw_progress(table, gop, 50);
 
-        let entry = e.header().entry_point();
+    let entry_point = load_kernel(table, &kernel_binary)
+        .unwrap_or_else(|err| panic!("Failed to load '{}': {}",
+                                     filename, err));
 
-        entry
-    } else {
-        0
+    // Remember the kernel we booted so the next boot can show it, the
+    // variable is only written when it changes to save the flash
+    let last_boot = variables.read(LAST_BOOT_VARIABLE, &POTATO_VARIABLE_GUID);
+    let changed = match last_boot {
+        Ok(last_boot) => last_boot.data != filename.as_bytes(),
+        Err(_) => true,
     };
 
-    type KernelEntry = extern "sysv64" fn(boot_info: &BootInfo) -> !;
+    let attributes = EFIVariableAttributes::NON_VOLATILE |
+        EFIVariableAttributes::BOOTSERVICE_ACCESS;
+    if changed {
+        if let Err(err) = variables.write(LAST_BOOT_VARIABLE,
+                                          &POTATO_VARIABLE_GUID,
+                                          attributes, filename.as_bytes())
+        {
+            println!("Failed to save the last boot: {}", err);
+        }
+    }
+
+    // Find the firmware tables the kernel needs before the boot
+    // services are gone
+    let acpi_rsdp = table.acpi_rsdp().unwrap_or(0) as u64;
+    let smbios_entry_point = table.smbios_entry_point().unwrap_or(0) as u64;
+    if acpi_rsdp == 0 {
+        println!("Failed to find the ACPI RSDP");
+    }
+
+    let _ = draw_progress(table, gop, 100);
+
+    // Write everything we have printed to the directory with the options
+    if let Some(boot_log) = &bootloader_options.boot_log {
+        let log = unsafe { WRITER.as_mut().and_then(|w| w.stop_log()) };
+        let log = log.unwrap_or_default();
+
+        if let Err(err) = write_file(&directories[0], boot_log, log.as_bytes())
+        {
+            println!("Failed to write the boot log '{}': {}", boot_log, err);
+        }
+    }
 
     println!("Entring the kernel");
 
-    let mut buffer = core::ptr::null_mut();
-    table.boot_services.allocate_pool(EFIMemoryType::BootServicesData,
-                                      core::mem::size_of::<BootInfo>(),
-                                      &mut buffer);
+    // NOTE(patrik): The kernel reads the boot info and the memory
+    // regions so they get their own memory type, the kernel knows that
+    // it can reuse the memory when it's done with them
+    let buffer = table.boot_services
+        .allocate_pool(EFIMemoryType::OsLoaderBootInfo,
+                       core::mem::size_of::<BootInfo>())
+        .expect("Failed to allocate the boot info");
 
     let boot_info = buffer as *mut BootInfo;
 
+    // The firmware stack is boot services data that the kernel sees as
+    // free memory so the kernel gets its own stack, it's reported as a
+    // kernel stack region and not with the boot info so the kernel
+    // doesn't free the stack it's running on
+    let kernel_stack = table.boot_services
+        .allocate_pages(AllocateType::AnyPages,
+                        EFIMemoryType::OsLoaderBootInfo,
+                        pages_for(KERNEL_STACK_SIZE))
+        .expect("Failed to allocate the kernel stack");
+
+    let memory_map = table.boot_services.memory_map()
+        .expect("Failed to get the memory map");
+    print_memory_summary(&memory_map);
+
+    // The regions the kernel needs to know about that the firmware
+    // doesn't have a type for
+    let framebuffer = PhysRange::new(
+        gop.mode.framebuffer_base.0,
+        pages_for(gop.mode.framebuffer_size as u64));
+    let special_ranges = [
+        MemoryRegion::new(framebuffer.start, framebuffer.size(),
+                          MemoryRegionKind::FRAMEBUFFER),
+        MemoryRegion::new(kernel_stack.start, kernel_stack.size(),
+                          MemoryRegionKind::KERNEL_STACK),
+    ];
+
+    // The map can have more regions when we exit so make room for some
+    // more, a range can split a region in to three regions
+    let region_capacity =
+        memory_map.len() + MEMORY_MAP_SLACK + special_ranges.len() * 2;
+    let region_size =
+        (region_capacity * core::mem::size_of::<MemoryRegion>()) as u64;
+    let region_range = table.boot_services
+        .allocate_pages(AllocateType::AnyPages,
+                        EFIMemoryType::OsLoaderBootInfo,
+                        pages_for(region_size))
+        .expect("Failed to allocate the memory regions");
+
+    // Zero is a valid region so clearing the memory makes the array valid
+    let regions = unsafe {
+        core::ptr::write_bytes(region_range.as_mut_ptr(), 0,
+                               region_range.size() as usize);
+        core::slice::from_raw_parts_mut(
+            region_range.as_mut_ptr() as *mut MemoryRegion, region_capacity)
+    };
 
-    let memory_map_size = table.boot_services.get_memory_map_size();
+    // NOTE(patrik): We can't allocate after exit_boot_services so the
+    // buffers for the final map are allocated now with some room for
+    // the descriptors the allocations adds. The firmware descriptors
+    // can be bigger than ours so the buffer can't have more descriptors
+    // than the capacity
+    let descriptor_size = core::mem::size_of::<MemoryDescriptor>();
+    let memory_map_size = table.boot_services.get_memory_map_size()
+        .expect("Failed to get the memory map size") +
+        MEMORY_MAP_SLACK * descriptor_size * 2;
     let mut memory_map_buffer = alloc::vec![0u8; memory_map_size];
+    let mut final_memory_map =
+        MemoryMap::with_capacity(memory_map_size / descriptor_size);
 
     let memory_map = loop {
-        let memory_map =
+        let memory_map = unsafe {
             table.boot_services.get_memory_map(memory_map_buffer.as_mut_ptr(),
-                                               memory_map_size);
+                                               memory_map_size)
+                .expect("Failed to get the memory map")
+        };
         let memory_key = memory_map.key();
 
         let result =
             table.boot_services.exit_boot_services(image_handle, memory_key);
 
         match result {
-            Some(_) => {
+            Ok(_) => {
                 break memory_map;
             }
 
-            None => {
+            // The memory map key is out of date so try again
+            Err(err) if err.status() == EFIStatus::InvalidParameter => {
                 println!("Failed to exit boot services, trying again");
                 continue;
             }
+
+            Err(err) => panic!("{}", err),
         }
     };
 
+    // The boot services are gone so we can't use the console
+    // or the allocator anymore
     unsafe {
-        let mut info = &mut *boot_info;
+        WRITER = None;
+        TABLE = None;
+    }
+
+    // The map can't be bigger than the buffer it was copied from
+    final_memory_map.fill(&memory_map)
+        .expect("Failed to copy the memory map");
+    final_memory_map.coalesce();
+
+    let firmware_regions = final_memory_map.iter().map(|descriptor| {
+        let range = descriptor.range();
+        MemoryRegion::new(range.start, range.size(), region_kind(descriptor))
+    });
+
+    let region_count =
+        build_memory_map(firmware_regions, &special_ranges, regions)
+        .expect("Too many regions in the memory map");
+
+    unsafe {
+        let info = &mut *boot_info;
         info.framebuffer.width = gop.mode.info.width;
         info.framebuffer.height = gop.mode.info.height;
         info.framebuffer.pixels_per_scanline =
             gop.mode.info.pixels_per_scanline;
+        info.framebuffer.format = pixel_format(gop.mode.info);
         info.framebuffer.base = gop.mode.framebuffer_base.0;
         info.framebuffer.size = gop.mode.framebuffer_size;
 
-        info.memory_map = memory_map;
+        info.memory_map =
+            MemoryRegions::from_raw_parts(regions.as_ptr(), region_count);
+        info.acpi_rsdp = acpi_rsdp;
+        info.smbios_entry_point = smbios_entry_point;
     }
 
-    let entry = entry_point as *const u64;
-    let entry: KernelEntry = unsafe { core::mem::transmute(entry) };
-
     // Call the kernel's entry point
-    unsafe { (entry)(&*boot_info) };
+    unsafe { enter_kernel(entry_point, &mut *boot_info, kernel_stack) };
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // NOTE(patrik): Only print to the console, the allocator can be the
+    // reason we panic so the log is leaked instead of freed
+    unsafe {
+        if let Some(writer) = WRITER.as_mut() {
+            core::mem::forget(writer.stop_log());
+        }
+    }
+
     println!("---------- BOOTLOADER PANIC ----------");
 
     if let Some(msg) = info.message() {
@@ -384,5 +1447,20 @@ fn panic(info: &PanicInfo) -> ! {
 
     println!("--------------------------------------");
 
+    let (table, runtime_services) = unsafe { (TABLE, RUNTIME_SERVICES) };
+
+    // Give the operator some time to read the message, stall is
+    // a boot service so we can only wait if we still have them
+    if let Some(table) = table {
+        println!("Rebooting in 10 seconds");
+        let _ = table.boot_services.stall(10 * 1000 * 1000);
+    }
+
+    // The runtime services works even after exit_boot_services
+    if let Some(runtime_services) = runtime_services {
+        runtime_services.reset_system(EFIResetType::Cold,
+                                      EFIStatus::Aborted);
+    }
+
     loop {}
 }
@@ -1,4 +1,4 @@
-use boot_common::Framebuffer;
+use boot_common::{ Framebuffer, Color };
 use spin::Mutex;
 
 const FONT_BYTES: &'static [u8] = include_bytes!("../res/zap-vga16.psf");
@@ -42,27 +42,16 @@ impl<'a> PSFFont<'a> {
         }
     }
 
-    fn put_char(&self, framebuffer: &Framebuffer, c: char, x: u32, y: u32) {
-        let pixel_ptr = framebuffer.base as *mut u32;
-
+    fn put_char(&self, framebuffer: &mut Framebuffer,
+                c: char, x: u32, y: u32)
+    {
         let mut offset = c as usize * self.char_size as usize;
 
-        let x = x as isize;
-        let y = y as isize;
-
         for yoff in 0..16 {
             for xoff in 0..8 {
                 let data = self.bytes[offset] as u8;
                 if (data & (0b10000000u8.wrapping_shr(xoff as u32))) > 0 {
-                    unsafe {
-                        let row_offset = (y + yoff) *
-                            framebuffer.pixels_per_scanline as isize;
-                        let pixel_offset =
-                            (x + xoff) + row_offset;
-
-                        core::ptr::write_volatile(
-                            pixel_ptr.offset(pixel_offset), 0xffffff);
-                    }
+                    framebuffer.put_pixel(x + xoff, y + yoff, Color::WHITE);
                 }
             }
 
@@ -78,13 +67,13 @@ struct Cursor {
 
 struct Writer<'a> {
     font: PSFFont<'a>,
-    framebuffer: &'a Framebuffer,
+    framebuffer: &'a mut Framebuffer,
 
     cursor: Cursor,
 }
 
 impl<'a> Writer<'a> {
-    fn new(font: PSFFont<'a>, framebuffer: &'a Framebuffer) -> Self {
+    fn new(font: PSFFont<'a>, framebuffer: &'a mut Framebuffer) -> Self {
         Self {
             font,
             framebuffer,
@@ -98,12 +87,14 @@ impl<'a> Writer<'a> {
 
     fn write_char(&mut self, c: char) {
         match c {
-            '\n' => {
-                self.cursor.x = 0;
-                self.cursor.y += 1;
-            }
+            '\n' => self.new_line(),
 
             _ => {
+                // Wrap the line if the character doesn't fit
+                if (self.cursor.x + 1) * 8 > self.framebuffer.width {
+                    self.new_line();
+                }
+
                 let x = self.cursor.x * 8;
                 let y = self.cursor.y * 16;
                 self.font.put_char(self.framebuffer, c, x, y);
@@ -112,6 +103,17 @@ impl<'a> Writer<'a> {
             }
         }
     }
+
+    fn new_line(&mut self) {
+        self.cursor.x = 0;
+        self.cursor.y += 1;
+
+        // Scroll the screen up a line if the cursor is below the screen
+        if (self.cursor.y + 1) * 16 > self.framebuffer.height {
+            self.framebuffer.scroll(16, Color::BLACK);
+            self.cursor.y -= 1;
+        }
+    }
 }
 
 impl<'a> core::fmt::Write for Writer<'a> {
@@ -143,17 +145,22 @@ macro_rules! println {
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
 
-    WRITER.lock().as_mut().unwrap().write_fmt(args).unwrap();
+    // Nothing to print to if there is no framebuffer
+    if let Some(writer) = WRITER.lock().as_mut() {
+        writer.write_fmt(args).unwrap();
+    }
 }
 
-pub fn init_graphics(framebuffer: &'static Framebuffer) {
-    unsafe {
-        core::ptr::write_bytes(framebuffer.base as *mut u8,
-                               0, framebuffer.size as usize);
+pub fn init_graphics(framebuffer: &'static mut Framebuffer) {
+    // NOTE(patrik): Without a linear framebuffer we have no way to draw
+    if !framebuffer.is_drawable() {
+        return;
     }
 
+    framebuffer.clear(Color::BLACK);
+
     let font = PSFFont::new(FONT_BYTES);
-    let writer = Writer::new(font, &framebuffer);
+    let writer = Writer::new(font, framebuffer);
 
     {
         *WRITER.lock() = Some(writer);
@@ -7,41 +7,32 @@ extern crate rlibc;
 extern crate boot_common;
 extern crate alloc;
 extern crate spin;
-extern crate uefi;
 
 mod graphics;
 
-use boot_common::{ BootInfo };
-use uefi::memory::{ EFIMemoryMap, EFIMemoryType };
+use boot_common::{ BootInfo, MemoryRegions, MemoryRegionKind };
 
 use core::panic::PanicInfo;
 
 use alloc::alloc::{ GlobalAlloc, Layout };
 
-fn print_memory_map(memory_map: &EFIMemoryMap) {
-    let mut memory_size = 0usize;
-    for entry in memory_map.entries() {
-        if entry.memory_type == EFIMemoryType::ConventionalMemory ||
-            entry.memory_type == EFIMemoryType::BootServicesCode ||
-            entry.memory_type == EFIMemoryType::BootServicesData {
-            memory_size += entry.number_of_pages as usize;
-        }
-    }
+fn print_memory_map(memory_map: &MemoryRegions) {
+    let memory_size = memory_map.total_size(MemoryRegionKind::USABLE);
 
-    println!("Total Pages: {}", memory_size);
-    println!("Total Memory: {} MiB", (memory_size * 4096) / 1024 / 1024);
+    println!("Total Pages: {}", memory_size / 4096);
+    println!("Total Memory: {} MiB", memory_size / 1024 / 1024);
+    println!("Highest Usable Address: {:#x}",
+             memory_map.highest_usable_address().unwrap_or(0));
 
-    for entry in memory_map.entries() {
-        if entry.memory_type == EFIMemoryType::ConventionalMemory ||
-            entry.memory_type == EFIMemoryType::LoaderCode ||
-            entry.memory_type == EFIMemoryType::LoaderData ||
-            entry.memory_type == EFIMemoryType::BootServicesCode ||
-            entry.memory_type == EFIMemoryType::BootServicesData
+    for region in memory_map.iter() {
+        if region.kind == MemoryRegionKind::USABLE ||
+            region.kind == MemoryRegionKind::BOOTLOADER_RECLAIMABLE ||
+            region.kind == MemoryRegionKind::KERNEL ||
+            region.kind == MemoryRegionKind::KERNEL_STACK
         {
-            let start = entry.physical_start.0;
-            let end =
-                (entry.physical_start.0 + entry.number_of_pages * 4096) - 1;
-            let size = end - start + 1;
+            let start = region.start;
+            let end = region.end() - 1;
+            let size = region.size;
 
             print!("[0x{:016x}-0x{:016x}] ", start, end);
 
@@ -55,7 +46,7 @@ fn print_memory_map(memory_map: &EFIMemoryMap) {
                 print!("{:>4} B", size);
             }
 
-            print!(" : {:?}", entry.memory_type);
+            print!(" : {:?}", region.kind);
 
             println!();
         }
@@ -64,12 +55,15 @@ fn print_memory_map(memory_map: &EFIMemoryMap) {
 
 #[no_mangle]
 #[link_section = ".boot"]
-extern fn kernel_entry(boot_info: &'static BootInfo) -> ! {
-    graphics::init_graphics(&boot_info.framebuffer);
+extern fn kernel_entry(boot_info: &'static mut BootInfo) -> ! {
+    graphics::init_graphics(&mut boot_info.framebuffer);
 
     println!("Welcome to the Example Kernel");
     print_memory_map(&boot_info.memory_map);
 
+    println!("ACPI RSDP: {:#x}", boot_info.acpi_rsdp);
+    println!("SMBIOS entry point: {:#x}", boot_info.smbios_entry_point);
+
     loop {}
 }
 
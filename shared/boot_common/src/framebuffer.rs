@@ -0,0 +1,428 @@
+/// The order of the color channels in a pixel
+///
+/// NOTE(patrik): This is a number and not a enum for the same reason as
+/// `MemoryRegionKind`, a layout the kernel doesn't know about should be
+/// treated as `BLT_ONLY`
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[repr(transparent)]
+pub struct PixelLayout(pub u32);
+
+impl PixelLayout {
+    /// 8 bits per channel with red in the lowest byte
+    pub const RGB: Self = Self(0);
+    /// 8 bits per channel with blue in the lowest byte
+    pub const BGR: Self = Self(1);
+    /// The channels are described by the masks in the pixel format
+    pub const BITMASK: Self = Self(2);
+    /// There is no linear framebuffer to draw to
+    pub const BLT_ONLY: Self = Self(3);
+}
+
+impl core::fmt::Debug for PixelLayout {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match *self {
+            Self::RGB => "Rgb",
+            Self::BGR => "Bgr",
+            Self::BITMASK => "Bitmask",
+            Self::BLT_ONLY => "BltOnly",
+            Self(layout) => return write!(f, "Unknown({})", layout),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Where a color channel is in a pixel
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct ColorChannel {
+    /// The bit the channel starts at
+    pub shift: u8,
+    /// The number of bits in the channel
+    pub size: u8,
+}
+
+impl ColorChannel {
+    /// Create a channel from the bits set in `mask`
+    pub fn from_mask(mask: u32) -> Self {
+        if mask == 0 {
+            return Self::default();
+        }
+
+        Self {
+            shift: mask.trailing_zeros() as u8,
+            size: mask.count_ones() as u8,
+        }
+    }
+
+    /// Scale the 8-bit `value` to the size of the channel and move it in
+    /// to the position of the channel
+    pub fn encode(&self, value: u8) -> u32 {
+        if self.size == 0 {
+            return 0;
+        }
+
+        // Scale so full intensity sets every bit in the channel
+        let max = (1u64 << self.size.min(32)) - 1;
+        let value = (value as u64 * max / 255) as u32;
+
+        value << self.shift
+    }
+}
+
+/// Describes how a pixel is encoded in the framebuffer
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct PixelFormat {
+    pub layout: PixelLayout,
+
+    pub red: ColorChannel,
+    pub green: ColorChannel,
+    pub blue: ColorChannel,
+
+    /// The number of bytes each pixel takes, 0 if the layout is
+    /// `BLT_ONLY`
+    pub bytes_per_pixel: u32,
+}
+
+impl PixelFormat {
+    /// Create a format from the masks of a `Bitmask` layout, the reserved
+    /// mask is needed to know the size of the pixel
+    pub fn from_masks(red: u32, green: u32, blue: u32, reserved: u32)
+        -> Self
+    {
+        // The pixel is as big as the highest bit in the masks, rounded up
+        // to whole bytes
+        let all = red | green | blue | reserved;
+
+        Self {
+            layout: PixelLayout::BITMASK,
+
+            red: ColorChannel::from_mask(red),
+            green: ColorChannel::from_mask(green),
+            blue: ColorChannel::from_mask(blue),
+
+            bytes_per_pixel: 4 - all.leading_zeros() / 8,
+        }
+    }
+
+    /// Create a format from the layout, `BITMASK` layouts should use
+    /// `from_masks`
+    pub fn from_layout(layout: PixelLayout) -> Self {
+        let channel = |shift| ColorChannel { shift, size: 8 };
+
+        let (red, green, blue, bytes_per_pixel) = match layout {
+            PixelLayout::RGB => (channel(0), channel(8), channel(16), 4),
+            PixelLayout::BGR => (channel(16), channel(8), channel(0), 4),
+            _ => {
+                let none = ColorChannel::default();
+                (none, none, none, 0)
+            }
+        };
+
+        Self {
+            layout,
+
+            red,
+            green,
+            blue,
+
+            bytes_per_pixel,
+        }
+    }
+
+    /// Encode a color to the value that should be written to the
+    /// framebuffer, only the lowest `bytes_per_pixel` bytes are used
+    pub fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        self.red.encode(red) |
+            self.green.encode(green) |
+            self.blue.encode(blue)
+    }
+}
+
+/// A color with 8 bits per channel, it's packed to the pixel format
+/// of the framebuffer when it's drawn
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::new(0x00, 0x00, 0x00);
+    pub const WHITE: Color = Color::new(0xff, 0xff, 0xff);
+
+    /// Create a color from the channels
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+        }
+    }
+}
+
+/// A linear framebuffer filled in by the bootloader, the drawing
+/// functions clip everything to the size of the framebuffer
+///
+/// NOTE(patrik): The drawing functions trusts that `base` and `size`
+/// describes memory we can write to, only the bootloader should create
+/// this struct
+#[derive(Debug)]
+#[repr(C)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels_per_scanline: u32,
+
+    pub format: PixelFormat,
+
+    pub base: u64,
+    pub size: u64,
+}
+
+impl Framebuffer {
+    /// Check if there is a linear framebuffer to draw to
+    pub fn is_drawable(&self) -> bool {
+        self.base != 0 && self.format.bytes_per_pixel != 0
+    }
+
+    /// The number of bytes in a scanline
+    fn stride(&self) -> usize {
+        self.pixels_per_scanline as usize *
+            self.format.bytes_per_pixel as usize
+    }
+
+    /// The byte offset of the start of row `y`, the offset is checked
+    /// so the whole row is inside of the framebuffer
+    fn row_offset(&self, y: u32) -> Option<usize> {
+        if !self.is_drawable() || y >= self.height {
+            return None;
+        }
+
+        let offset = y as usize * self.stride();
+        let len = self.width as usize * self.format.bytes_per_pixel as usize;
+        if offset + len > self.size as usize {
+            return None;
+        }
+
+        Some(offset)
+    }
+
+    /// Get the bytes of the visible pixels in row `y`
+    pub fn row(&self, y: u32) -> Option<&[u8]> {
+        let offset = self.row_offset(y)?;
+        let len = self.width as usize * self.format.bytes_per_pixel as usize;
+
+        unsafe {
+            let ptr = (self.base as *const u8).add(offset);
+            Some(core::slice::from_raw_parts(ptr, len))
+        }
+    }
+
+    /// Get the bytes of the visible pixels in row `y` to write to
+    pub fn row_mut(&mut self, y: u32) -> Option<&mut [u8]> {
+        let offset = self.row_offset(y)?;
+        let len = self.width as usize * self.format.bytes_per_pixel as usize;
+
+        unsafe {
+            let ptr = (self.base as *mut u8).add(offset);
+            Some(core::slice::from_raw_parts_mut(ptr, len))
+        }
+    }
+
+    /// Write a packed pixel to `row` at `x`, `row` has to be from
+    /// `row_mut`
+    fn write_pixel(row: &mut [u8], x: usize, pixel: &[u8]) {
+        let start = x * pixel.len();
+
+        // NOTE(patrik): The framebuffer can be uncached memory so the
+        // compiler is not allowed to merge or remove the writes
+        for (dest, byte) in row[start..start + pixel.len()]
+            .iter_mut()
+            .zip(pixel)
+        {
+            unsafe { core::ptr::write_volatile(dest, *byte) };
+        }
+    }
+
+    /// Pack the color to the bytes to write to the framebuffer
+    fn pack(&self, color: Color) -> ([u8; 4], usize) {
+        let pixel = self.format.encode(color.red, color.green, color.blue);
+        (pixel.to_le_bytes(), self.format.bytes_per_pixel as usize)
+    }
+
+    /// Draw a single pixel, the pixel is skipped if it's outside of the
+    /// framebuffer
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: Color) {
+        if x >= self.width {
+            return;
+        }
+
+        let (pixel, len) = self.pack(color);
+        if let Some(row) = self.row_mut(y) {
+            Self::write_pixel(row, x as usize, &pixel[..len]);
+        }
+    }
+
+    /// Fill a rectangle with a color, the rectangle is clipped to the
+    /// framebuffer
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32,
+                     color: Color)
+    {
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+
+        let (pixel, len) = self.pack(color);
+        for y in y..y_end {
+            if let Some(row) = self.row_mut(y) {
+                for x in x..x_end {
+                    Self::write_pixel(row, x as usize, &pixel[..len]);
+                }
+            }
+        }
+    }
+
+    /// Copy a rectangle to another place in the framebuffer, the
+    /// rectangles can overlap and both are clipped to the framebuffer
+    pub fn copy_rect(&mut self, src_x: u32, src_y: u32,
+                     dest_x: u32, dest_y: u32,
+                     width: u32, height: u32)
+    {
+        // Clip the size so both rectangles are inside of the framebuffer
+        let width = width
+            .min(self.width.saturating_sub(src_x))
+            .min(self.width.saturating_sub(dest_x));
+        let height = height
+            .min(self.height.saturating_sub(src_y))
+            .min(self.height.saturating_sub(dest_y));
+
+        if width == 0 || height == 0 || !self.is_drawable() {
+            return;
+        }
+
+        let bytes_per_pixel = self.format.bytes_per_pixel as usize;
+        let len = width as usize * bytes_per_pixel;
+
+        let mut copy_row = |row: u32| {
+            let src = self.row_offset(src_y + row);
+            let dest = self.row_offset(dest_y + row);
+
+            if let (Some(src), Some(dest)) = (src, dest) {
+                let src = src + src_x as usize * bytes_per_pixel;
+                let dest = dest + dest_x as usize * bytes_per_pixel;
+
+                // The rows can overlap so this needs to be a memmove
+                unsafe {
+                    let base = self.base as *mut u8;
+                    core::ptr::copy(base.add(src), base.add(dest), len);
+                }
+            }
+        };
+
+        // Copy the rows in the order that doesn't overwrite the rows we
+        // haven't copied yet
+        if dest_y > src_y {
+            (0..height).rev().for_each(&mut copy_row);
+        } else {
+            (0..height).for_each(&mut copy_row);
+        }
+    }
+
+    /// Scroll the whole framebuffer up by `lines` pixels and fill the
+    /// uncovered lines at the bottom with `color`
+    pub fn scroll(&mut self, lines: u32, color: Color) {
+        let lines = lines.min(self.height);
+
+        self.copy_rect(0, lines, 0, 0, self.width, self.height - lines);
+        self.fill_rect(0, self.height - lines, self.width, lines, color);
+    }
+
+    /// Fill the whole framebuffer with a color
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_masks_finds_the_channels() {
+        let format = PixelFormat::from_masks(0x00ff0000, 0x0000ff00,
+                                             0x000000ff, 0xff000000);
+
+        assert_eq!(format.layout, PixelLayout::BITMASK);
+        assert_eq!(format.red, ColorChannel { shift: 16, size: 8 });
+        assert_eq!(format.green, ColorChannel { shift: 8, size: 8 });
+        assert_eq!(format.blue, ColorChannel { shift: 0, size: 8 });
+        assert_eq!(format.bytes_per_pixel, 4);
+    }
+
+    #[test]
+    fn from_masks_sizes_the_pixel_from_the_highest_bit() {
+        // 5:6:5 without reserved bits fits in two bytes
+        let format = PixelFormat::from_masks(0xf800, 0x07e0, 0x001f, 0);
+        assert_eq!(format.red, ColorChannel { shift: 11, size: 5 });
+        assert_eq!(format.green, ColorChannel { shift: 5, size: 6 });
+        assert_eq!(format.blue, ColorChannel { shift: 0, size: 5 });
+        assert_eq!(format.bytes_per_pixel, 2);
+
+        // 10:10:10 with 2 reserved bits
+        let format = PixelFormat::from_masks(0x3ff00000, 0x000ffc00,
+                                             0x000003ff, 0xc0000000);
+        assert_eq!(format.red, ColorChannel { shift: 20, size: 10 });
+        assert_eq!(format.bytes_per_pixel, 4);
+    }
+
+    #[test]
+    fn from_masks_with_a_missing_channel() {
+        let format = PixelFormat::from_masks(0xff0000, 0xff00, 0, 0);
+
+        assert_eq!(format.blue, ColorChannel::default());
+        assert_eq!(format.encode(0xff, 0xff, 0xff), 0xffff00);
+        assert_eq!(format.bytes_per_pixel, 3);
+    }
+
+    #[test]
+    fn encode_scales_to_the_channel_size() {
+        let channel = |size| ColorChannel { shift: 0, size };
+
+        // 1-bit channels are only on at full intensity
+        assert_eq!(channel(1).encode(0x00), 0);
+        assert_eq!(channel(1).encode(0xfe), 0);
+        assert_eq!(channel(1).encode(0xff), 1);
+
+        assert_eq!(channel(5).encode(0x00), 0);
+        assert_eq!(channel(5).encode(0x80), 0x0f);
+        assert_eq!(channel(5).encode(0xff), 0x1f);
+
+        assert_eq!(channel(8).encode(0x00), 0);
+        assert_eq!(channel(8).encode(0x80), 0x80);
+        assert_eq!(channel(8).encode(0xff), 0xff);
+
+        // Full intensity sets every bit of a wide channel
+        assert_eq!(channel(10).encode(0x00), 0);
+        assert_eq!(channel(10).encode(0x80), 0x201);
+        assert_eq!(channel(10).encode(0xff), 0x3ff);
+
+        assert_eq!(ColorChannel::default().encode(0xff), 0);
+    }
+
+    #[test]
+    fn encode_moves_the_value_to_the_channel() {
+        let channel = ColorChannel { shift: 20, size: 10 };
+        assert_eq!(channel.encode(0xff), 0x3ff << 20);
+
+        let format = PixelFormat::from_layout(PixelLayout::BGR);
+        assert_eq!(format.encode(0x11, 0x22, 0x33), 0x112233);
+    }
+
+    #[test]
+    fn unknown_layouts_have_no_pixels() {
+        let format = PixelFormat::from_layout(PixelLayout(42));
+        assert_eq!(format.bytes_per_pixel, 0);
+    }
+}
@@ -1,28 +1,27 @@
-#![no_std]
-
-/// Library for common stuff between the bootloader and the kernel
-/// Because the bootloader and kernel might use diffrent file format i.e
-/// the bootloader is a PE executable and the kernel might be ELF we need
-/// to ensure that the bootinfo struct is the same for both
+//! Library for common stuff between the bootloader and the kernel
+//! Because the bootloader and kernel might use diffrent file format i.e
+//! the bootloader is a PE executable and the kernel might be ELF we need
+//! to ensure that the bootinfo struct is the same for both
 
-extern crate uefi;
-
-use uefi::memory::{ EFIMemoryMap };
+#![no_std]
 
-#[derive(Debug)]
-#[repr(C)]
-pub struct Framebuffer {
-    pub width: u32,
-    pub height: u32,
-    pub pixels_per_scanline: u32,
+pub mod framebuffer;
+pub mod memory;
 
-    pub base: u64,
-    pub size: u64,
-}
+pub use crate::framebuffer::{ Framebuffer, Color };
+pub use crate::framebuffer::{ PixelFormat, PixelLayout, ColorChannel };
+pub use crate::memory::{ MemoryRegions, MemoryRegion, MemoryRegionKind };
 
 #[derive(Debug)]
 #[repr(C)]
-pub struct BootInfo<'a> {
+pub struct BootInfo {
     pub framebuffer: Framebuffer,
-    pub memory_map: EFIMemoryMap<'a>
+    /// The memory map after `exit_boot_services`
+    pub memory_map: MemoryRegions,
+
+    /// Physical address of the ACPI RSDP, 0 if the firmware didn't have one
+    pub acpi_rsdp: u64,
+    /// Physical address of the SMBIOS entry point, 0 if the firmware
+    /// didn't have one
+    pub smbios_entry_point: u64,
 }
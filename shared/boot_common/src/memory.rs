@@ -0,0 +1,353 @@
+/// What a region of physical memory is used for, the kinds doesn't
+/// depend on the firmware the bootloader runs on
+///
+/// NOTE(patrik): This is a number and not a enum so a kernel built
+/// against an older version of this crate can still read a map with
+/// kinds it doesn't know about, those should be treated as reserved
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[repr(transparent)]
+pub struct MemoryRegionKind(pub u32);
+
+impl MemoryRegionKind {
+    /// Free memory
+    pub const USABLE: Self = Self(0);
+    /// Memory the bootloader gives to the kernel i.e the boot info and
+    /// this map, free when the kernel is done with them
+    pub const BOOTLOADER_RECLAIMABLE: Self = Self(1);
+    /// The segments of the kernel
+    pub const KERNEL: Self = Self(2);
+    /// Files the bootloader loaded for the kernel
+    pub const MODULES: Self = Self(3);
+    /// The framebuffer in the boot info
+    pub const FRAMEBUFFER: Self = Self(4);
+    /// The ACPI tables, free after the kernel have read the tables
+    pub const ACPI_RECLAIMABLE: Self = Self(5);
+    /// Memory the firmware needs for ACPI, can't be used by the kernel
+    pub const ACPI_NVS: Self = Self(6);
+    /// Memory the kernel can't use i.e the firmware runtime services
+    /// and memory mapped devices
+    pub const RESERVED: Self = Self(7);
+    /// Memory with errors
+    pub const BAD: Self = Self(8);
+    /// The stack the kernel starts on, free after the kernel have switched
+    /// to another stack
+    pub const KERNEL_STACK: Self = Self(9);
+}
+
+impl core::fmt::Debug for MemoryRegionKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match *self {
+            Self::USABLE => "Usable",
+            Self::BOOTLOADER_RECLAIMABLE => "BootloaderReclaimable",
+            Self::KERNEL => "Kernel",
+            Self::MODULES => "Modules",
+            Self::FRAMEBUFFER => "Framebuffer",
+            Self::ACPI_RECLAIMABLE => "AcpiReclaimable",
+            Self::ACPI_NVS => "AcpiNvs",
+            Self::RESERVED => "Reserved",
+            Self::BAD => "Bad",
+            Self::KERNEL_STACK => "KernelStack",
+            Self(kind) => return write!(f, "Unknown({})", kind),
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A range of physical memory
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct MemoryRegion {
+    /// The address of the first byte
+    pub start: u64,
+    /// The size in bytes
+    pub size: u64,
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    pub const fn new(start: u64, size: u64, kind: MemoryRegionKind) -> Self {
+        Self {
+            start,
+            size,
+            kind,
+        }
+    }
+
+    /// The address after the last byte in the region
+    pub fn end(&self) -> u64 {
+        self.start + self.size
+    }
+}
+
+/// The memory map the bootloader gives to the kernel, the regions are
+/// sorted by the address and don't overlap
+#[derive(Debug)]
+#[repr(C)]
+pub struct MemoryRegions {
+    regions: *const MemoryRegion,
+    count: u64,
+}
+
+impl MemoryRegions {
+    /// Create the map from a array of regions
+    ///
+    /// # Safety
+    /// The array needs to have `count` regions and be valid for as long
+    /// as the map is used
+    pub unsafe fn from_raw_parts(regions: *const MemoryRegion, count: usize)
+        -> Self
+    {
+        Self {
+            regions,
+            count: count as u64,
+        }
+    }
+
+    /// The regions in the map
+    pub fn as_slice(&self) -> &[MemoryRegion] {
+        if self.regions.is_null() {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(self.regions, self.count as usize)
+        }
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, MemoryRegion> {
+        self.as_slice().iter()
+    }
+
+    /// The number of bytes with a kind
+    pub fn total_size(&self, kind: MemoryRegionKind) -> u64 {
+        self.iter()
+            .filter(|region| region.kind == kind)
+            .map(|region| region.size)
+            .sum()
+    }
+
+    /// The address after the last byte of usable memory
+    pub fn highest_usable_address(&self) -> Option<u64> {
+        self.iter()
+            .filter(|region| region.kind == MemoryRegionKind::USABLE)
+            .map(|region| region.end())
+            .max()
+    }
+}
+
+/// Add a region after the last region, a region next to the last region
+/// with the same kind is merged in to it. None if there is no room
+fn push_region(regions: &mut [MemoryRegion], count: &mut usize,
+               region: MemoryRegion)
+    -> Option<()>
+{
+    if region.size == 0 {
+        return Some(());
+    }
+
+    if *count > 0 {
+        let previous = &mut regions[*count - 1];
+        if previous.kind == region.kind && previous.end() == region.start {
+            previous.size += region.size;
+            return Some(());
+        }
+    }
+
+    *regions.get_mut(*count)? = region;
+    *count += 1;
+
+    Some(())
+}
+
+/// Build the memory map for the kernel from the sorted `firmware` regions,
+/// the parts of the firmware regions inside one of the `ranges` gets the
+/// kind of the range and the parts of the ranges the firmware doesn't
+/// know about are added. Returns the number of regions written or None if
+/// they don't fit in `regions`
+///
+/// NOTE(patrik): The bootloader runs this after exit_boot_services so it
+/// can't allocate
+pub fn build_memory_map<I>(firmware: I, ranges: &[MemoryRegion],
+                           regions: &mut [MemoryRegion])
+    -> Option<usize>
+    where I: Iterator<Item = MemoryRegion> + Clone
+{
+    let contains = |range: &MemoryRegion, address: u64| {
+        address >= range.start && address < range.end()
+    };
+
+    let mut count = 0;
+    for region in firmware.clone() {
+        // Split the region where the ranges starts and ends
+        let mut current = region.start;
+        while current < region.end() {
+            let inside = ranges.iter()
+                .find(|range| contains(range, current));
+
+            let (end, kind) = match inside {
+                Some(range) => (range.end().min(region.end()), range.kind),
+
+                None => {
+                    let end = ranges.iter()
+                        .map(|range| range.start)
+                        .filter(|&start| start > current)
+                        .fold(region.end(), u64::min);
+                    (end, region.kind)
+                }
+            };
+
+            let part = MemoryRegion::new(current, end - current, kind);
+            push_region(regions, &mut count, part)?;
+
+            current = end;
+        }
+    }
+
+    // The framebuffer is usually not in the firmware map so add the parts
+    // of the ranges between the firmware regions
+    for range in ranges {
+        let mut current = range.start;
+        for region in firmware.clone() {
+            if region.end() <= current || region.start >= range.end() {
+                continue;
+            }
+
+            if region.start > current {
+                let gap = MemoryRegion::new(current, region.start - current,
+                                            range.kind);
+                push_region(regions, &mut count, gap)?;
+            }
+
+            current = region.end();
+        }
+
+        if current < range.end() {
+            let gap = MemoryRegion::new(current, range.end() - current,
+                                        range.kind);
+            push_region(regions, &mut count, gap)?;
+        }
+    }
+
+    regions[..count].sort_unstable_by_key(|region| region.start);
+
+    Some(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: MemoryRegion = MemoryRegion::new(0, 0, MemoryRegionKind(0));
+
+    fn build(firmware: &[MemoryRegion], ranges: &[MemoryRegion])
+        -> Option<([MemoryRegion; 16], usize)>
+    {
+        let mut regions = [EMPTY; 16];
+        let count = build_memory_map(firmware.iter().copied(), ranges,
+                                     &mut regions)?;
+
+        Some((regions, count))
+    }
+
+    fn region(start: u64, size: u64, kind: MemoryRegionKind)
+        -> MemoryRegion
+    {
+        MemoryRegion::new(start, size, kind)
+    }
+
+    #[test]
+    fn regions_straddling_a_range_are_split() {
+        let firmware = [
+            region(0x1000, 0x9000, MemoryRegionKind::USABLE),
+        ];
+        let ranges = [
+            region(0x4000, 0x2000, MemoryRegionKind::KERNEL),
+        ];
+
+        let (regions, count) = build(&firmware, &ranges).unwrap();
+        assert_eq!(&regions[..count], [
+            region(0x1000, 0x3000, MemoryRegionKind::USABLE),
+            region(0x4000, 0x2000, MemoryRegionKind::KERNEL),
+            region(0x6000, 0x4000, MemoryRegionKind::USABLE),
+        ]);
+    }
+
+    #[test]
+    fn ranges_straddling_regions_are_split() {
+        // The boot info range covers the end of one region and the start
+        // of the next
+        let firmware = [
+            region(0x1000, 0x3000, MemoryRegionKind::USABLE),
+            region(0x4000, 0x3000, MemoryRegionKind::RESERVED),
+        ];
+        let ranges = [
+            region(0x3000, 0x2000,
+                   MemoryRegionKind::BOOTLOADER_RECLAIMABLE),
+        ];
+
+        let (regions, count) = build(&firmware, &ranges).unwrap();
+        assert_eq!(&regions[..count], [
+            region(0x1000, 0x2000, MemoryRegionKind::USABLE),
+            region(0x3000, 0x2000,
+                   MemoryRegionKind::BOOTLOADER_RECLAIMABLE),
+            region(0x5000, 0x2000, MemoryRegionKind::RESERVED),
+        ]);
+    }
+
+    #[test]
+    fn ranges_outside_the_firmware_map_are_added() {
+        let firmware = [
+            region(0x1000, 0x1000, MemoryRegionKind::USABLE),
+            region(0x4000, 0x1000, MemoryRegionKind::USABLE),
+        ];
+        let ranges = [
+            region(0x8000, 0x1000, MemoryRegionKind::FRAMEBUFFER),
+            region(0x1800, 0x3000, MemoryRegionKind::KERNEL_STACK),
+        ];
+
+        let (regions, count) = build(&firmware, &ranges).unwrap();
+        assert_eq!(&regions[..count], [
+            region(0x1000, 0x800, MemoryRegionKind::USABLE),
+            region(0x1800, 0x800, MemoryRegionKind::KERNEL_STACK),
+            region(0x2000, 0x2000, MemoryRegionKind::KERNEL_STACK),
+            region(0x4000, 0x800, MemoryRegionKind::KERNEL_STACK),
+            region(0x4800, 0x800, MemoryRegionKind::USABLE),
+            region(0x8000, 0x1000, MemoryRegionKind::FRAMEBUFFER),
+        ]);
+    }
+
+    #[test]
+    fn adjacent_regions_with_the_same_kind_are_merged() {
+        let firmware = [
+            region(0x1000, 0x1000, MemoryRegionKind::USABLE),
+            region(0x2000, 0x1000, MemoryRegionKind::USABLE),
+            region(0x3000, 0x1000, MemoryRegionKind::KERNEL),
+        ];
+        let ranges = [
+            region(0x4000, 0x1000, MemoryRegionKind::KERNEL),
+        ];
+
+        let (regions, count) = build(&firmware, &ranges).unwrap();
+        assert_eq!(&regions[..count], [
+            region(0x1000, 0x2000, MemoryRegionKind::USABLE),
+            region(0x3000, 0x2000, MemoryRegionKind::KERNEL),
+        ]);
+    }
+
+    #[test]
+    fn too_many_regions_is_an_error() {
+        let firmware = [
+            region(0x1000, 0x1000, MemoryRegionKind::USABLE),
+            region(0x2000, 0x1000, MemoryRegionKind::RESERVED),
+        ];
+
+        let mut regions = [EMPTY; 1];
+        assert_eq!(build_memory_map(firmware.iter().copied(), &[],
+                                    &mut regions), None);
+
+        let mut regions = [EMPTY; 2];
+        assert_eq!(build_memory_map(firmware.iter().copied(), &[],
+                                    &mut regions), Some(2));
+    }
+}
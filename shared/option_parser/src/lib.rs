@@ -3,15 +3,11 @@
 #![allow(dead_code)]
 
 #[derive(PartialEq, Copy, Clone, Debug)]
-pub enum Category {
+pub enum Category<'a> {
     Bootloader,
     Kernel,
-}
-
-impl Default for Category {
-    fn default() -> Self {
-        Self::Bootloader
-    }
+    /// A named boot entry from a '[entry NAME]' section
+    Entry(&'a str),
 }
 
 pub struct OptionParser<'a> {
@@ -25,10 +21,15 @@ impl<'a> OptionParser<'a> {
         }
     }
 
+    /// Parse the options from a options file, the options are in
+    /// `[bootloader]`, `[kernel]` or `[entry NAME]` sections. The lines in
+    /// a unknown or malformed section and the lines without a '=' are
+    /// skipped so a bad line doesn't stop the boot
     pub fn options<F>(&self, mut func: F) -> Option<()>
-        where F: FnMut(Category, &str, &str) -> Option<()>
+        where F: FnMut(Category<'a>, &'a str, &'a str) -> Option<()>
     {
-        let mut current_category = Category::default();
+        // The options before the first section are bootloader options
+        let mut current_category = Some(Category::Bootloader);
 
         for line in self.text.lines() {
             let line = line.trim();
@@ -37,32 +38,186 @@ impl<'a> OptionParser<'a> {
                 continue;
             }
 
-            if &line[0..1] == "[" {
-                let category = &line[1..line.len() - 1];
+            if let Some(header) = line.strip_prefix('[') {
+                let category = header.strip_suffix(']');
 
-                match category {
-                    "bootloader" => {
-                        current_category = Category::Bootloader;
+                current_category = match category {
+                    Some("bootloader") => Some(Category::Bootloader),
+                    Some("kernel") => Some(Category::Kernel),
+                    Some(category) if category.starts_with("entry ") => {
+                        let name = category["entry ".len()..].trim();
+                        Some(Category::Entry(name))
                     }
+                    _ => None,
+                };
+            } else if let Some(category) = current_category {
+                let index = match line.find('=') {
+                    Some(index) => index,
+                    None => continue,
+                };
 
-                    "kernel" => {
-                        current_category = Category::Kernel;
-                    }
+                let key = &line[0..index];
+                let value = &line[index+1..];
 
-                    _ => {
-                        panic!("Unknown category");
-                    }
-                }
+                func(category, key, value)?;
+            }
+        }
+
+        Some(())
+    }
+
+    /// Parse the options from a command line i.e
+    /// `timeout=0 kernel.debug="yes please"`, the options with a `kernel.`
+    /// prefix are kernel options and the rest are bootloader options.
+    /// Values can be quoted to have spaces, the quotes are removed from
+    /// the bootloader values. Words without a '=' are skipped i.e the
+    /// name of the image the UEFI shell gives us
+    pub fn command_line<F>(&self, mut func: F) -> Option<()>
+        where F: FnMut(Category<'a>, &'a str, &'a str) -> Option<()>
+    {
+        let mut option = |word: &'a str| {
+            let index = match word.find('=') {
+                Some(index) => index,
+                None => return Some(()),
+            };
+
+            let key = &word[0..index];
+            let value = &word[index+1..];
+
+            if let Some(key) = key.strip_prefix("kernel.") {
+                func(Category::Kernel, key, value)
             } else {
-                let index = line.find('=')?;
+                // The bootloader values are used as they are so remove
+                // the quotes, the kernel gets the quotes
+                let value = value.strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
 
-                let key = &line[0..index];
-                let value = &line[index+1..];
+                func(Category::Bootloader, key, value)
+            }
+        };
 
-                func(current_category, key, value)?;
+        let mut in_quotes = false;
+        let mut start = None;
+
+        for (index, c) in self.text.char_indices() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            }
+
+            if c.is_whitespace() && !in_quotes {
+                if let Some(start) = start.take() {
+                    option(&self.text[start..index])?;
+                }
+            } else if start.is_none() {
+                start = Some(index);
             }
         }
 
+        if let Some(start) = start {
+            option(&self.text[start..])?;
+        }
+
         Some(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn options(text: &str) -> Vec<(Category<'_>, &str, &str)> {
+        let mut result = Vec::new();
+        OptionParser::new(text).options(|category, key, value| {
+            result.push((category, key, value));
+            Some(())
+        }).unwrap();
+
+        result
+    }
+
+    fn command_line(text: &str) -> Vec<(Category<'_>, &str, &str)> {
+        let mut result = Vec::new();
+        OptionParser::new(text).command_line(|category, key, value| {
+            result.push((category, key, value));
+            Some(())
+        }).unwrap();
+
+        result
+    }
+
+    #[test]
+    fn options_sections() {
+        let text = "timeout=5\n\
+                    [kernel]\n\
+                    debug=1\n\
+                    \n\
+                    [entry Shell]\n\
+                    chainload=shell.efi\n\
+                    [bootloader]\n\
+                    kernel_filename=kernel.elf\n";
+
+        assert_eq!(options(text), [
+            (Category::Bootloader, "timeout", "5"),
+            (Category::Kernel, "debug", "1"),
+            (Category::Entry("Shell"), "chainload", "shell.efi"),
+            (Category::Bootloader, "kernel_filename", "kernel.elf"),
+        ]);
+    }
+
+    #[test]
+    fn options_skip_unknown_and_malformed_sections() {
+        let text = "[unknown]\n\
+                    a=1\n\
+                    [kernel\n\
+                    b=2\n\
+                    [\n\
+                    c=3\n\
+                    [kernel]\n\
+                    d=4\n";
+
+        assert_eq!(options(text), [(Category::Kernel, "d", "4")]);
+    }
+
+    #[test]
+    fn options_skip_lines_without_equals() {
+        assert_eq!(options("garbage\ntimeout=0\n"),
+                   [(Category::Bootloader, "timeout", "0")]);
+    }
+
+    #[test]
+    fn command_line_categories() {
+        assert_eq!(command_line("timeout=0 kernel.debug=1"), [
+            (Category::Bootloader, "timeout", "0"),
+            (Category::Kernel, "debug", "1"),
+        ]);
+    }
+
+    #[test]
+    fn command_line_quoted_values() {
+        let text = r#"config="my options.txt" kernel.root="a b""#;
+
+        // The bootloader gets the value without the quotes and the kernel
+        // gets the quotes
+        assert_eq!(command_line(text), [
+            (Category::Bootloader, "config", "my options.txt"),
+            (Category::Kernel, "root", "\"a b\""),
+        ]);
+    }
+
+    #[test]
+    fn command_line_skips_words_without_equals() {
+        assert_eq!(command_line(r"\EFI\boot\bootx64.efi timeout=3"),
+                   [(Category::Bootloader, "timeout", "3")]);
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(options("").is_empty());
+        assert!(command_line("").is_empty());
+        assert!(command_line("   ").is_empty());
+    }
+}
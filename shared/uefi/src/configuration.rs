@@ -0,0 +1,55 @@
+use crate::EFIGuid;
+
+/// GUID for the ACPI 1.0 RSDP
+pub const ACPI_TABLE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0xeb9d2d30,
+        data2: 0x2d88,
+        data3: 0x11d3,
+        data4: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]
+    };
+
+/// GUID for the ACPI 2.0 RSDP
+pub const ACPI_20_TABLE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0x8868e871,
+        data2: 0xe4f1,
+        data3: 0x11d3,
+        data4: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81]
+    };
+
+/// GUID for the 32-bit SMBIOS entry point
+pub const SMBIOS_TABLE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0xeb9d2d31,
+        data2: 0x2d88,
+        data3: 0x11d3,
+        data4: [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d]
+    };
+
+/// GUID for the 64-bit SMBIOS 3 entry point
+pub const SMBIOS3_TABLE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0xf2fd1544,
+        data2: 0x9794,
+        data3: 0x4a2c,
+        data4: [0x99, 0x2e, 0xe5, 0xbb, 0xcf, 0x20, 0xe3, 0x94]
+    };
+
+/// GUID for the flattened device tree
+pub const DEVICE_TREE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0xb1b621d5,
+        data2: 0xf19c,
+        data3: 0x41a5,
+        data4: [0x83, 0x0b, 0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0]
+    };
+
+/// A entry in the configuration table, the vendor table is the address
+/// of the table the guid represents i.e the ACPI RSDP
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EFIConfigurationTable {
+    pub vendor_guid: EFIGuid,
+    pub vendor_table: usize,
+}
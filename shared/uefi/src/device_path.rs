@@ -0,0 +1,466 @@
+use crate::{ EFIGuid, EFIResult, Protocol };
+use crate::string::CString16;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// GUID for the DevicePath protocol
+pub const DEVICE_PATH_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0x09576e91,
+        data2: 0x6d3f,
+        data3: 0x11d2,
+        data4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]
+    };
+
+// The types of the device path nodes
+const HARDWARE_DEVICE_PATH:  u8 = 0x01;
+const ACPI_DEVICE_PATH:      u8 = 0x02;
+const MESSAGING_DEVICE_PATH: u8 = 0x03;
+const MEDIA_DEVICE_PATH:     u8 = 0x04;
+const END_DEVICE_PATH:       u8 = 0x7f;
+
+// The sub types of the device path nodes we know about
+const HW_PCI_DP:             u8 = 0x01;
+const ACPI_DP:               u8 = 0x01;
+const MSG_USB_DP:            u8 = 0x05;
+const MSG_SATA_DP:           u8 = 0x12;
+const MEDIA_HARDDRIVE_DP:    u8 = 0x01;
+const MEDIA_FILEPATH_DP:     u8 = 0x04;
+const END_INSTANCE_DP:       u8 = 0x01;
+const END_ENTIRE_DP:         u8 = 0xff;
+
+/// The size of the header in front of every node
+const NODE_HEADER_SIZE: usize = 4;
+
+/// The header of a device path node, a device path is a list of nodes
+/// in memory that ends with a end node. The protocol interface the
+/// firmware gives us is the first node
+#[repr(C)]
+#[derive(Debug)]
+pub struct EFIDevicePathProtocol {
+    typ: u8,
+    sub_typ: u8,
+    length: [u8; 2],
+}
+
+unsafe impl Protocol for EFIDevicePathProtocol {
+    const GUID: EFIGuid = DEVICE_PATH_GUID;
+}
+
+impl EFIDevicePathProtocol {
+    /// Iterate over the nodes in the path, the end node of the whole
+    /// path is not included
+    pub fn nodes(&self) -> DevicePathNodes<'_> {
+        DevicePathNodes {
+            next: self as *const EFIDevicePathProtocol as *const u8,
+            done: false,
+            _marker: core::marker::PhantomData,
+        }
+    }
+}
+
+impl core::fmt::Display for EFIDevicePathProtocol {
+    /// Format the path like the DevicePathToText protocol does i.e
+    /// PciRoot(0x0)/Pci(0x1,0x1)/Ata(0x0)/HD(1,GPT,...)/\EFI\BOOT
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let mut separator = false;
+
+        for node in self.nodes() {
+            // The instances are separated by ',' and the nodes in a
+            // instance are separated by '/'
+            if let DeviceNode::EndInstance = node.kind() {
+                f.write_char(',')?;
+                separator = false;
+                continue;
+            }
+
+            if separator {
+                f.write_char('/')?;
+            }
+            separator = true;
+
+            write!(f, "{}", node.kind())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A node in a device path
+#[derive(Clone, Copy, Debug)]
+pub struct DevicePathNode<'a> {
+    typ: u8,
+    sub_typ: u8,
+    data: &'a [u8],
+}
+
+impl<'a> DevicePathNode<'a> {
+    /// The type of the node
+    pub fn node_type(&self) -> u8 {
+        self.typ
+    }
+
+    /// The sub type of the node
+    pub fn sub_type(&self) -> u8 {
+        self.sub_typ
+    }
+
+    /// The data after the node header
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Parse the node in to a typed node, the nodes we don't know or
+    /// that are too short are `DeviceNode::Unknown`
+    pub fn kind(&self) -> DeviceNode<'a> {
+        let data = self.data;
+        let unknown = DeviceNode::Unknown {
+            typ: self.typ,
+            sub_typ: self.sub_typ,
+            data,
+        };
+
+        match (self.typ, self.sub_typ) {
+            (HARDWARE_DEVICE_PATH, HW_PCI_DP) if data.len() >= 2 => {
+                DeviceNode::Pci {
+                    function: data[0],
+                    device: data[1],
+                }
+            }
+
+            (ACPI_DEVICE_PATH, ACPI_DP) if data.len() >= 8 => {
+                DeviceNode::Acpi {
+                    hid: read_u32(data, 0),
+                    uid: read_u32(data, 4),
+                }
+            }
+
+            (MESSAGING_DEVICE_PATH, MSG_USB_DP) if data.len() >= 2 => {
+                DeviceNode::Usb {
+                    parent_port: data[0],
+                    interface: data[1],
+                }
+            }
+
+            (MESSAGING_DEVICE_PATH, MSG_SATA_DP) if data.len() >= 6 => {
+                DeviceNode::Sata {
+                    port: read_u16(data, 0),
+                    multiplier_port: read_u16(data, 2),
+                    lun: read_u16(data, 4),
+                }
+            }
+
+            (MEDIA_DEVICE_PATH, MEDIA_HARDDRIVE_DP) if data.len() >= 38 => {
+                let mut signature = [0u8; 16];
+                signature.copy_from_slice(&data[20..36]);
+
+                // The signature type is after the partition format
+                let signature = match data[37] {
+                    0x01 =>
+                        PartitionSignature::Mbr(read_u32(&signature, 0)),
+                    0x02 =>
+                        PartitionSignature::Gpt(guid_from_bytes(&signature)),
+                    _ => PartitionSignature::None,
+                };
+
+                DeviceNode::HardDrive {
+                    partition_number: read_u32(data, 0),
+                    partition_start: read_u64(data, 4),
+                    partition_size: read_u64(data, 12),
+                    signature,
+                }
+            }
+
+            (MEDIA_DEVICE_PATH, MEDIA_FILEPATH_DP) => {
+                // The path is a null-terminated UCS-2 string, the data
+                // isn't aligned for u16 so read it in bytes
+                let path = data.chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .map(|c| {
+                        core::char::from_u32(c as u32)
+                            .unwrap_or(core::char::REPLACEMENT_CHARACTER)
+                    })
+                    .collect();
+
+                DeviceNode::FilePath(path)
+            }
+
+            (END_DEVICE_PATH, END_INSTANCE_DP) => DeviceNode::EndInstance,
+            (END_DEVICE_PATH, END_ENTIRE_DP) => DeviceNode::End,
+
+            _ => unknown,
+        }
+    }
+}
+
+/// Iterator over the nodes in a device path, created by
+/// `EFIDevicePathProtocol::nodes`
+pub struct DevicePathNodes<'a> {
+    next: *const u8,
+    done: bool,
+    _marker: core::marker::PhantomData<&'a EFIDevicePathProtocol>,
+}
+
+impl<'a> Iterator for DevicePathNodes<'a> {
+    type Item = DevicePathNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (typ, sub_typ, length) = unsafe {
+            let header = &*(self.next as *const EFIDevicePathProtocol);
+            (header.typ, header.sub_typ,
+             u16::from_le_bytes(header.length) as usize)
+        };
+
+        // Stop at the end of the path, also stop if the length is
+        // broken so we don't loop forever on the same node
+        if (typ == END_DEVICE_PATH && sub_typ == END_ENTIRE_DP) ||
+            length < NODE_HEADER_SIZE
+        {
+            self.done = true;
+            return None;
+        }
+
+        let data = unsafe {
+            core::slice::from_raw_parts(self.next.add(NODE_HEADER_SIZE),
+                                        length - NODE_HEADER_SIZE)
+        };
+
+        self.next = unsafe { self.next.add(length) };
+
+        Some(DevicePathNode {
+            typ,
+            sub_typ,
+            data,
+        })
+    }
+}
+
+/// The signature of the partition a hard drive node points to
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum PartitionSignature {
+    None,
+    Mbr(u32),
+    Gpt(EFIGuid),
+}
+
+/// A typed device path node
+#[derive(PartialEq, Clone, Debug)]
+pub enum DeviceNode<'a> {
+    Pci {
+        function: u8,
+        device: u8,
+    },
+    Acpi {
+        hid: u32,
+        uid: u32,
+    },
+    Usb {
+        parent_port: u8,
+        interface: u8,
+    },
+    Sata {
+        port: u16,
+        multiplier_port: u16,
+        lun: u16,
+    },
+    HardDrive {
+        partition_number: u32,
+        partition_start: u64,
+        partition_size: u64,
+        signature: PartitionSignature,
+    },
+    FilePath(String),
+    EndInstance,
+    End,
+    Unknown {
+        typ: u8,
+        sub_typ: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> core::fmt::Display for DeviceNode<'a> {
+    /// Format the node like the DevicePathToText protocol does
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            DeviceNode::Pci { function, device } =>
+                write!(f, "Pci({:#x},{:#x})", device, function),
+
+            DeviceNode::Acpi { hid, uid } => {
+                // The ids with the PNP vendor have short names
+                match *hid {
+                    0x0a0341d0 => write!(f, "PciRoot({:#x})", uid),
+                    0x0a0841d0 => write!(f, "PcieRoot({:#x})", uid),
+                    _ => {
+                        f.write_str("Acpi(")?;
+                        write_eisa_id(f, *hid)?;
+                        write!(f, ",{:#x})", uid)
+                    }
+                }
+            }
+
+            DeviceNode::Usb { parent_port, interface } =>
+                write!(f, "USB({:#x},{:#x})", parent_port, interface),
+
+            DeviceNode::Sata { port, multiplier_port, lun } =>
+                write!(f, "Sata({:#x},{:#x},{:#x})",
+                       port, multiplier_port, lun),
+
+            DeviceNode::HardDrive { partition_number, partition_start,
+                                    partition_size, signature } => {
+                write!(f, "HD({},", partition_number)?;
+
+                match signature {
+                    PartitionSignature::Mbr(signature) =>
+                        write!(f, "MBR,{:#010x},", signature)?,
+                    PartitionSignature::Gpt(guid) =>
+                        write!(f, "GPT,{},", guid)?,
+                    PartitionSignature::None =>
+                        f.write_str("0,0,")?,
+                }
+
+                write!(f, "{:#x},{:#x})", partition_start, partition_size)
+            }
+
+            DeviceNode::FilePath(path) => f.write_str(path),
+
+            DeviceNode::EndInstance | DeviceNode::End => Ok(()),
+
+            DeviceNode::Unknown { typ, sub_typ, data } => {
+                write!(f, "Path({},{},", typ, sub_typ)?;
+
+                for byte in data.iter() {
+                    write!(f, "{:02X}", byte)?;
+                }
+
+                f.write_char(')')
+            }
+        }
+    }
+}
+
+/// A owned device path, used to build new paths i.e the path to a file
+/// on the device we booted from
+#[derive(Clone, Debug)]
+pub struct DevicePathBuf {
+    /// The nodes in the path without the end node
+    bytes: Vec<u8>,
+    /// The nodes with the end node, the path we give to the firmware
+    bytes_with_end: Vec<u8>,
+}
+
+impl DevicePathBuf {
+    /// Create a copy of a path
+    pub fn from_path(path: &EFIDevicePathProtocol) -> Self {
+        let mut result = Self {
+            bytes: Vec::new(),
+            bytes_with_end: Vec::new(),
+        };
+
+        for node in path.nodes() {
+            result.push_node(node.typ, node.sub_typ, node.data);
+        }
+
+        result.update_end();
+
+        result
+    }
+
+    /// Add a raw node to the end of the path
+    fn push_node(&mut self, typ: u8, sub_typ: u8, data: &[u8]) {
+        let length = (NODE_HEADER_SIZE + data.len()) as u16;
+
+        self.bytes.push(typ);
+        self.bytes.push(sub_typ);
+        self.bytes.extend_from_slice(&length.to_le_bytes());
+        self.bytes.extend_from_slice(data);
+    }
+
+    /// Update the path with the end node after the path changed
+    fn update_end(&mut self) {
+        self.bytes_with_end.clear();
+        self.bytes_with_end.extend_from_slice(&self.bytes);
+        self.bytes_with_end.extend_from_slice(
+            &[END_DEVICE_PATH, END_ENTIRE_DP, NODE_HEADER_SIZE as u8, 0]);
+    }
+
+    /// Add a file path node to the end of the path, the path should use
+    /// '\' as the separator
+    pub fn push_file_path(&mut self, path: &str) -> EFIResult<()> {
+        let path: CString16 = path.parse()?;
+
+        let data: Vec<u8> = path.as_slice_with_nul()
+            .iter()
+            .flat_map(|c| c.to_le_bytes().to_vec())
+            .collect();
+
+        self.push_node(MEDIA_DEVICE_PATH, MEDIA_FILEPATH_DP, &data);
+        self.update_end();
+
+        Ok(())
+    }
+
+    /// The path to give to the firmware
+    pub fn as_device_path(&self) -> &EFIDevicePathProtocol {
+        // NOTE(patrik): The header only have bytes so the alignment of
+        // the buffer is fine
+        unsafe {
+            &*(self.bytes_with_end.as_ptr() as *const EFIDevicePathProtocol)
+        }
+    }
+}
+
+impl core::fmt::Display for DevicePathBuf {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.as_device_path().fmt(f)
+    }
+}
+
+/// Write a compressed EISA id i.e PNP0A03
+fn write_eisa_id(f: &mut core::fmt::Formatter, id: u32) -> core::fmt::Result {
+    // The vendor is 3 letters with 5 bits each in the low 16 bits
+    let vendor = id & 0xffff;
+    for shift in &[10, 5, 0] {
+        let c = ((vendor >> shift) & 0x1f) as u8;
+        f.write_char((b'A' - 1 + c) as char)?;
+    }
+
+    write!(f, "{:04X}", id >> 16)
+}
+
+/// Read a little endian u16 from the data
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+/// Read a little endian u32 from the data
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+
+    u32::from_le_bytes(bytes)
+}
+
+/// Read a little endian u64 from the data
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+
+    u64::from_le_bytes(bytes)
+}
+
+/// Create a guid from the bytes it's stored as in memory
+fn guid_from_bytes(bytes: &[u8; 16]) -> EFIGuid {
+    let mut data4 = [0u8; 8];
+    data4.copy_from_slice(&bytes[8..16]);
+
+    EFIGuid::new(read_u32(bytes, 0), read_u16(bytes, 4), read_u16(bytes, 6),
+                 data4)
+}
@@ -0,0 +1,64 @@
+use crate::{ EFIStatus, EFIGuid };
+
+/// Result type used by all the wrappers in this library
+pub type EFIResult<T> = Result<T, EFIError>;
+
+/// Error returned when a UEFI call fails, it contains the status
+/// the firmware returned, the name of the operation that failed and
+/// the guid of the protocol if the operation was on a protocol
+#[derive(Clone, Copy, Debug)]
+pub struct EFIError {
+    status: EFIStatus,
+    operation: &'static str,
+    guid: Option<EFIGuid>,
+}
+
+impl EFIError {
+    /// Create a new error from a status and the operation that failed
+    pub fn new(status: EFIStatus, operation: &'static str) -> Self {
+        Self {
+            status,
+            operation,
+            guid: None,
+        }
+    }
+
+    /// Attach the guid of the protocol the operation was on
+    pub fn with_guid(mut self, guid: &EFIGuid) -> Self {
+        self.guid = Some(*guid);
+        self
+    }
+
+    /// The status the firmware returned
+    pub fn status(&self) -> EFIStatus {
+        self.status
+    }
+
+    /// The name of the operation that failed
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// The guid of the protocol if the operation was on a protocol
+    pub fn guid(&self) -> Option<&EFIGuid> {
+        self.guid.as_ref()
+    }
+
+    /// Check if the error is only a warning, the operation was
+    /// completed but the result might not be what was expected
+    pub fn is_warning(&self) -> bool {
+        self.status.is_warning()
+    }
+}
+
+impl core::fmt::Display for EFIError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "'{}' failed with {:?}", self.operation, self.status)?;
+
+        if let Some(guid) = &self.guid {
+            write!(f, " (protocol {})", guid)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use crate::BootServices;
+
+use core::ffi::c_void;
+
+/// The number of timer ticks in a second, timers use 100ns units
+pub const TIMER_TICKS_PER_SECOND: u64 = 10_000_000;
+
+/// A handle to a event, the event can be owned by the firmware
+/// i.e the event for pending keys or created by `create_event`
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[repr(transparent)]
+pub struct EFIEvent(usize);
+
+// Flags for the type of event to create
+bitflags! {
+    pub struct EFIEventType: u32 {
+        const TIMER                         = 0x80000000;
+        const RUNTIME                       = 0x40000000;
+        const NOTIFY_WAIT                   = 0x00000100;
+        const NOTIFY_SIGNAL                 = 0x00000200;
+        const SIGNAL_EXIT_BOOT_SERVICES     = 0x00000201;
+        const SIGNAL_VIRTUAL_ADDRESS_CHANGE = 0x60000202;
+    }
+}
+
+/// Task priority levels the notify functions can run at
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(usize)]
+pub enum EFITpl {
+    Application = 4,
+    Callback    = 8,
+    Notify      = 16,
+    HighLevel   = 31,
+}
+
+/// The type of timer to set with `set_timer`
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(C)]
+pub enum EFITimerDelay {
+    Cancel   = 0,
+    Periodic = 1,
+    Relative = 2,
+}
+
+/// Function called by the firmware when a event is signaled or waited on
+pub type EFIEventNotify = unsafe fn(event: EFIEvent, context: *mut c_void);
+
+/// A event created by `BootServices::create_event`, the event is closed
+/// when the guard is dropped
+pub struct EventGuard<'a> {
+    boot_services: &'a BootServices,
+    event: EFIEvent,
+}
+
+impl<'a> EventGuard<'a> {
+    /// Create a new guard for a created event
+    pub(crate) fn new(boot_services: &'a BootServices, event: EFIEvent)
+        -> Self
+    {
+        Self {
+            boot_services,
+            event,
+        }
+    }
+
+    /// The handle to the event
+    pub fn event(&self) -> EFIEvent {
+        self.event
+    }
+}
+
+impl<'a> Drop for EventGuard<'a> {
+    fn drop(&mut self) {
+        // NOTE(patrik): Nothing we can do if the close fails
+        let _ = self.boot_services.close_event(self.event);
+    }
+}
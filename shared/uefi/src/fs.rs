@@ -1,4 +1,13 @@
-use crate::{ EFIStatus, EFIGuid, EFITime };
+use crate::{ EFIStatus, EFIGuid, EFITime, EFIError, EFIResult, Protocol };
+
+use crate::string::{ CStr16, CString16 };
+
+mod path;
+
+pub use self::path::Path;
+
+use alloc::string::{ String, ToString };
+use alloc::vec::Vec;
 
 /// GUID for the SimpleFilesystem protocol
 pub const SIMPLE_FILESYSTEM_GUID: EFIGuid =
@@ -18,11 +27,37 @@ pub const GET_INFO_GUID: EFIGuid =
         data4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]
     };
 
+// Flags for how a file should be opened by `EFIFileHandle::open`
+bitflags! {
+    pub struct FileMode: u64 {
+        const READ   = 0x0000000000000001;
+        const WRITE  = 0x0000000000000002;
+        const CREATE = 0x8000000000000000;
+    }
+}
+
+// Flags for the attributes of a file, used when a file is created
+bitflags! {
+    pub struct FileAttribute: u64 {
+        const READ_ONLY  = 0x0000000000000001;
+        const HIDDEN     = 0x0000000000000002;
+        const SYSTEM     = 0x0000000000000004;
+        const RESERVED   = 0x0000000000000008;
+        const DIRECTORY  = 0x0000000000000010;
+        const ARCHIVE    = 0x0000000000000020;
+        const VALID_ATTR = 0x0000000000000037;
+    }
+}
+
+/// The position `set_position` moves to when seeking to the end of
+/// the file
+const END_OF_FILE_POSITION: u64 = 0xffffffffffffffff;
+
 /// Struct containing infomation for a file in the SimpleFilesystem
 /// NOTE(patrik): This struct is missing the filename because it is behind
 /// this struct in memory and the C code uses variable length arrays
-/// for structs and rust don't have that feature but there could
-/// be an easy fix if we want the filename from the file
+/// for structs and rust don't have that feature, `DirEntry` has the
+/// filename for the files in a directory
 #[derive(Copy, Clone, Debug)]
 #[repr(C)]
 pub struct EFIFileInfo {
@@ -37,6 +72,146 @@ pub struct EFIFileInfo {
     // NOTE(patrik): MISSING FILENAME
 }
 
+impl EFIFileInfo {
+    /// The attributes of the file
+    pub fn attributes(&self) -> FileAttribute {
+        FileAttribute::from_bits_truncate(self.attribute)
+    }
+}
+
+/// A file or directory in a directory read by `EFIFileHandle::read_dir`
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_size: u64,
+    pub physical_size: u64,
+    pub create_time: EFITime,
+    pub last_access_time: EFITime,
+    pub modification_time: EFITime,
+    pub attributes: FileAttribute,
+}
+
+impl DirEntry {
+    /// Create a entry from the raw file info buffer with the filename
+    /// behind the struct. The error status is `CompromisedData` if the
+    /// buffer is too small for the info the firmware says it has
+    fn from_info_buffer(buffer: &[u8]) -> EFIResult<Self> {
+        let info_size = core::mem::size_of::<EFIFileInfo>();
+        if buffer.len() < info_size {
+            return Err(EFIError::new(EFIStatus::CompromisedData,
+                                     "DirEntry::from_info_buffer"));
+        }
+
+        let info = unsafe {
+            core::ptr::read_unaligned(buffer.as_ptr() as *const EFIFileInfo)
+        };
+
+        // The size includes the filename
+        let size = info.size as usize;
+        if size < info_size || size > buffer.len() {
+            return Err(EFIError::new(EFIStatus::CompromisedData,
+                                     "DirEntry::from_info_buffer"));
+        }
+
+        // The filename is a null-terminated UTF-16 string behind the
+        // struct, the buffer is not aligned for u16 so read it in bytes
+        let mut name: Vec<u16> = buffer[info_size..size]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect();
+        name.push(0);
+
+        let name = CStr16::from_u16_with_nul(&name)
+            .map(String::from)
+            .unwrap_or_default();
+
+        Ok(Self {
+            name,
+            file_size: info.file_size,
+            physical_size: info.physical_size,
+            create_time: info.create_time,
+            last_access_time: info.last_access_time,
+            modification_time: info.modification_time,
+            attributes: info.attributes(),
+        })
+    }
+
+    /// Check if the entry is a directory
+    pub fn is_directory(&self) -> bool {
+        self.attributes.contains(FileAttribute::DIRECTORY)
+    }
+}
+
+/// Iterator over the entries in a directory, created by
+/// `Directory::read_dir`
+pub struct ReadDir<'a> {
+    directory: &'a EFIFileHandle,
+    buffer: Vec<u8>,
+    done: bool,
+}
+
+impl<'a> ReadDir<'a> {
+    /// Read the next raw entry, returns None at the end of the directory
+    fn read_entry(&mut self) -> EFIResult<Option<DirEntry>> {
+        loop {
+            let mut buffer_size = self.buffer.len() as u64;
+
+            let status = unsafe {
+                (self.directory.read_fn)(self.directory, &mut buffer_size,
+                                         self.buffer.as_mut_ptr())
+            };
+
+            // The firmware tells us how big the buffer needs to be for the
+            // entry so grow the buffer and try again, if the buffer
+            // can't grow we would never stop trying
+            if status == EFIStatus::BufferTooSmall &&
+                buffer_size as usize > self.buffer.len()
+            {
+                self.buffer.resize(buffer_size as usize, 0);
+                continue;
+            }
+
+            status.into_result("ReadDir::next")?;
+
+            // A read of 0 bytes means that there are no more entries
+            if buffer_size == 0 {
+                return Ok(None);
+            }
+
+            let buffer = &self.buffer[..buffer_size as usize];
+
+            return DirEntry::from_info_buffer(buffer).map(Some);
+        }
+    }
+}
+
+impl<'a> Iterator for ReadDir<'a> {
+    type Item = EFIResult<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.read_entry() {
+                Ok(Some(entry)) => {
+                    // Skip the entries for the directory and the parent
+                    if entry.name != "." && entry.name != ".." {
+                        return Some(Ok(entry));
+                    }
+                }
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    // Stop on the first error so we don't loop forever
+                    // on a broken directory
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+
+        None
+    }
+}
+
 /// An EFI Handle for a directory or file, this struct contains function
 /// pointers to muniplulate the directory or file and we create wrappers
 /// for those function so we have a better api because all these
@@ -50,20 +225,27 @@ pub struct EFIFileHandle {
                        filename: *const u16,
                        open_mode: u64,
                        attributes: u64) -> EFIStatus,
-    close_fn: usize,
-    delete_fn: usize,
+    close_fn: unsafe fn(this: &EFIFileHandle) -> EFIStatus,
+    delete_fn: unsafe fn(this: &EFIFileHandle) -> EFIStatus,
     read_fn: unsafe fn(this: &EFIFileHandle,
                        buffer_size: &mut u64,
                        buffer: *mut u8) -> EFIStatus,
-    write_fn: usize,
-    get_position_fn: usize,
-    set_position_fn: usize,
+    write_fn: unsafe fn(this: &EFIFileHandle,
+                        buffer_size: &mut u64,
+                        buffer: *const u8) -> EFIStatus,
+    get_position_fn: unsafe fn(this: &EFIFileHandle,
+                               position: &mut u64) -> EFIStatus,
+    set_position_fn: unsafe fn(this: &EFIFileHandle,
+                               position: u64) -> EFIStatus,
     get_info_fn: unsafe fn(this: &EFIFileHandle,
                            infomation_type: &EFIGuid,
                            buffer_size: &mut u64,
                            buffer: *mut u8) -> EFIStatus,
-    set_info_fn: usize,
-    flush_fn: usize,
+    set_info_fn: unsafe fn(this: &EFIFileHandle,
+                           infomation_type: &EFIGuid,
+                           buffer_size: u64,
+                           buffer: *const u8) -> EFIStatus,
+    flush_fn: unsafe fn(this: &EFIFileHandle) -> EFIStatus,
     open_ex_fn: usize,
     read_ex_fn: usize,
     write_ex_fn: usize,
@@ -71,52 +253,179 @@ pub struct EFIFileHandle {
 }
 
 impl EFIFileHandle {
-    /// Attempt to open a directory or file and return a handle
-    /// TODO(patrik): Return a result with a error and remove the panics
-    pub fn open<'a>(&self, filename: &str,
-                    open_mode: u64, attribute: u64)
-        -> &'a EFIFileHandle
+    /// Attempt to open a directory or file and return a handle, the
+    /// attributes are only used if the file is created
+    fn open<'a>(&self, filename: &CStr16,
+                open_mode: FileMode, attributes: FileAttribute)
+        -> EFIResult<&'a EFIFileHandle>
     {
-        // Create a buffer to construct a UTF-16 filename,
-        // used because Rust strings are UTF-8 and uefi only accepts UTF-16
-        let mut filename_buffer = [0u16; 1024];
-
-        // Loop through the filename and convert the characters to UTF-16
-        let mut index = 0;
-        for c in filename.bytes() {
-            filename_buffer[index] = c as u16;
-            index += 1;
-        }
-
-        // Null-Terminate the buffer just to be sure
-        filename_buffer[index] = 0u16;
-
         // Create a null handle ptr
         let mut handle_ptr = core::ptr::null_mut();
         // Try to open the filename
         let status = unsafe {
             (self.open_fn)(self, &mut handle_ptr,
-                            filename_buffer.as_ptr(), open_mode, attribute)
+                            filename.as_ptr(),
+                            open_mode.bits(), attributes.bits())
         };
 
-        // Check if the status if a success otherwise panic
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove this panic
-            panic!("'EFIFileHandle' failed to open '{}' error: {:?}",
-                   filename, status)
-        }
+        // Check if the status if a success
+        status.into_result("EFIFileHandle::open")?;
 
         // Dereference the handle pointer so we get a reference handle
         let handle = unsafe { &*handle_ptr };
 
         // Return the handle
-        handle
+        Ok(handle)
+    }
+
+    /// Close the handle, writes are flushed before the handle is closed
+    ///
+    /// # Safety
+    /// The handle is freed by the firmware so it can't be used after
+    /// it's closed
+    unsafe fn close(&self) -> EFIResult<()> {
+        let status = (self.close_fn)(self);
+
+        status.into_result("EFIFileHandle::close")
+    }
+
+    /// Delete the file and close the handle, the handle is closed even if
+    /// the file couldn't be deleted
+    ///
+    /// # Safety
+    /// The handle is freed by the firmware so it can't be used after
+    /// it's deleted
+    unsafe fn delete(&self) -> EFIResult<()> {
+        let status = (self.delete_fn)(self);
+
+        // NOTE(patrik): The firmware reports a failed delete as the
+        // warning WarnDeleteFailure so the warning is a error here
+        status.into_result("EFIFileHandle::delete")
+    }
+
+    /// Read from the current position in to the buffer and return the
+    /// number of bytes read, 0 means that we are at the end of the file
+    fn read(&self, buffer: &mut [u8]) -> EFIResult<usize> {
+        let mut buffer_size = buffer.len() as u64;
+
+        let status = unsafe {
+            (self.read_fn)(self, &mut buffer_size, buffer.as_mut_ptr())
+        };
+
+        status.into_result("EFIFileHandle::read")?;
+
+        Ok(buffer_size as usize)
+    }
+
+    /// Write the buffer at the current position and return the number of
+    /// bytes written, the file grows if we write past the end
+    fn write(&self, buffer: &[u8]) -> EFIResult<usize> {
+        let mut buffer_size = buffer.len() as u64;
+
+        let status = unsafe {
+            (self.write_fn)(self, &mut buffer_size, buffer.as_ptr())
+        };
+
+        status.into_result("EFIFileHandle::write")?;
+
+        Ok(buffer_size as usize)
+    }
+
+    /// Write the whole buffer at the current position
+    fn write_all(&self, mut buffer: &[u8]) -> EFIResult<()> {
+        while !buffer.is_empty() {
+            let written = self.write(buffer)?;
+
+            // The firmware should write everything or fail, but make
+            // sure we don't loop forever if it doesn't
+            if written == 0 {
+                return Err(EFIError::new(EFIStatus::DeviceError,
+                                         "EFIFileHandle::write_all"));
+            }
+
+            buffer = &buffer[written..];
+        }
+
+        Ok(())
+    }
+
+    /// Flush the writes to the device
+    fn flush(&self) -> EFIResult<()> {
+        let status = unsafe { (self.flush_fn)(self) };
+
+        status.into_result("EFIFileHandle::flush")
+    }
+
+    /// Get the current position in the file
+    fn get_position(&self) -> EFIResult<u64> {
+        let mut position = 0;
+
+        let status = unsafe {
+            (self.get_position_fn)(self, &mut position)
+        };
+
+        status.into_result("EFIFileHandle::get_position")?;
+
+        Ok(position)
+    }
+
+    /// Set the current position in the file, only 0 is valid for
+    /// directories and it restarts the directory read
+    fn set_position(&self, position: u64) -> EFIResult<()> {
+        let status = unsafe {
+            (self.set_position_fn)(self, position)
+        };
+
+        status.into_result("EFIFileHandle::set_position")
+    }
+
+    /// Move the current position to the end of the file i.e to append to
+    /// the file
+    fn seek_to_end(&self) -> EFIResult<()> {
+        self.set_position(END_OF_FILE_POSITION)
+    }
+
+    /// Change the size of the file, the file is truncated or grown
+    /// with zeros
+    fn set_size(&self, size: u64) -> EFIResult<()> {
+        // The firmware needs the whole info with the filename so get the
+        // raw buffer and change the size in it
+        let mut buffer = self.get_info_buffer()?;
+
+        unsafe {
+            let info = buffer.as_mut_ptr() as *mut EFIFileInfo;
+            let mut file_info = core::ptr::read_unaligned(info);
+            file_info.file_size = size;
+            core::ptr::write_unaligned(info, file_info);
+        }
+
+        let status = unsafe {
+            (self.set_info_fn)(self,
+                               &GET_INFO_GUID,
+                               buffer.len() as u64,
+                               buffer.as_ptr())
+        };
+
+        status.into_result("EFIFileHandle::set_size")
+    }
+
+    /// Iterate over the entries in the directory, the iterator starts
+    /// from the first entry and skips the '.' and '..' entries
+    fn read_dir(&self) -> EFIResult<ReadDir<'_>> {
+        // Restart the directory read
+        self.set_position(0)?;
+
+        Ok(ReadDir {
+            directory: self,
+            buffer: vec![0u8; core::mem::size_of::<EFIFileInfo>() + 256],
+            done: false,
+        })
     }
 
     /// Read the file and put all it's content to a buffer
-    pub fn read_to_buffer(&self) -> alloc::vec::Vec<u8> {
+    fn read_to_buffer(&self) -> EFIResult<Vec<u8>> {
         // Get the file info
-        let file_info = self.get_info();
+        let file_info = self.get_info()?;
 
         // Allocate a buffer for the file content
         let mut buffer = vec![0u8; file_info.file_size as usize];
@@ -130,17 +439,28 @@ impl EFIFileHandle {
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove the panic
-            panic!("'EFIFileHandle::read_to_buffer' failed to read error: {:?}",
-                   status);
-        }
+        status.into_result("EFIFileHandle::read_to_buffer")?;
 
-        buffer
+        // Only keep the bytes the firmware actually read
+        buffer.truncate(buffer_size as usize);
+
+        Ok(buffer)
+    }
+
+    /// Get the file info
+    fn get_info(&self) -> EFIResult<EFIFileInfo> {
+        let buffer = self.get_info_buffer()?;
+
+        // Cast the buffer pointer to a EFIFileInfo and copy it
+        let file_info = unsafe {
+            core::ptr::read_unaligned(buffer.as_ptr() as *const EFIFileInfo)
+        };
+
+        Ok(file_info)
     }
 
-    // Get the file info
-    pub fn get_info(&self) -> EFIFileInfo {
+    /// Get the raw file info buffer with the filename behind the struct
+    fn get_info_buffer(&self) -> EFIResult<Vec<u8>> {
         // Create a variable to retrive the required size for the buffer
         let mut buffer_size = 0u64;
 
@@ -155,9 +475,13 @@ impl EFIFileHandle {
 
         // Check the status becuase we expect it to be a BufferTooSmall error
         if status != EFIStatus::BufferTooSmall {
-            // TODO(patrik): Remove the panic
-            panic!("'EFIFileHandle::get_info' expected BufferTooSmall got {:?}",
-                   status);
+            status.into_result("EFIFileHandle::get_info")?;
+        }
+
+        // Make sure the firmware gives us enough room for the struct
+        let min_size = core::mem::size_of::<EFIFileInfo>() as u64;
+        if buffer_size < min_size {
+            buffer_size = min_size;
         }
 
         // Allocate a buffer for the info
@@ -175,15 +499,12 @@ impl EFIFileHandle {
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove the panic
-            panic!("'EFIFIleHandle::get_info' error: {:?}", status);
-        }
+        status.into_result("EFIFileHandle::get_info")?;
 
-        // Cast the buffer pointer to a EFIFileInfo and copy it
-        let file_info = unsafe { *(buffer.as_ptr() as *const EFIFileInfo) };
+        // Only keep the bytes the firmware actually wrote
+        buffer.truncate(buffer_size as usize);
 
-        file_info
+        Ok(buffer)
     }
 }
 
@@ -197,9 +518,13 @@ pub struct EFISimpleFilesystem {
                         -> EFIStatus,
 }
 
+unsafe impl Protocol for EFISimpleFilesystem {
+    const GUID: EFIGuid = SIMPLE_FILESYSTEM_GUID;
+}
+
 impl EFISimpleFilesystem {
-    /// Open the root volume and return a handle for it
-    pub fn open_volume<'a>(&self) -> &'a EFIFileHandle {
+    /// Open the root directory of the volume
+    pub fn open_volume(&self) -> EFIResult<Directory<'_>> {
         // Create a null handle
         let mut handle_ptr = core::ptr::null_mut();
 
@@ -209,15 +534,283 @@ impl EFISimpleFilesystem {
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove the panic
-            panic!("'EFISimpleFilesystem::open_volume' failed to open root volume - error: {:?}", status);
-        }
+        status.into_result("EFISimpleFilesystem::open_volume")?;
 
         // Dereference the pointer and get the reference to the handle
         let handle = unsafe { &*handle_ptr };
 
-        // Return the handle
-        handle
+        Ok(Directory::new(handle))
+    }
+}
+
+/// Open a handle from `directory` and check that the handle is a
+/// directory or a file, the handle is closed if it's the wrong type
+fn open_checked<'a>(directory: &EFIFileHandle, filename: &str,
+                    open_mode: FileMode, attributes: FileAttribute,
+                    want_directory: bool)
+    -> EFIResult<&'a EFIFileHandle>
+{
+    // Normalize the path so both '/' and '\' can be used
+    let filename: CString16 = Path::new(filename).to_string().parse()?;
+    let handle = directory.open(&filename, open_mode, attributes)?;
+
+    let is_directory = handle.get_info()
+        .map(|info| info.attributes().contains(FileAttribute::DIRECTORY));
+
+    match is_directory {
+        Ok(is_directory) if is_directory == want_directory => Ok(handle),
+        result => {
+            // NOTE(patrik): Nothing we can do if the close fails
+            unsafe {
+                let _ = handle.close();
+            }
+
+            result?;
+
+            Err(EFIError::new(EFIStatus::InvalidParameter,
+                              "EFIFileHandle::open"))
+        }
+    }
+}
+
+/// A opened file, the handle is closed when the file is dropped. The
+/// lifetime is tied to the filesystem the file was opened from
+pub struct File<'a> {
+    handle: &'a EFIFileHandle,
+}
+
+impl<'a> File<'a> {
+    /// Create a file for a opened handle
+    fn new(handle: &'a EFIFileHandle) -> Self {
+        Self {
+            handle,
+        }
+    }
+
+    /// Read from the current position in to the buffer and return the
+    /// number of bytes read, 0 means that we are at the end of the file
+    pub fn read(&self, buffer: &mut [u8]) -> EFIResult<usize> {
+        self.handle.read(buffer)
+    }
+
+    /// Read the whole file in to a buffer
+    pub fn read_to_end(&self) -> EFIResult<Vec<u8>> {
+        self.handle.read_to_buffer()
+    }
+
+    /// Write the buffer at the current position and return the number of
+    /// bytes written, the file grows if we write past the end
+    pub fn write(&self, buffer: &[u8]) -> EFIResult<usize> {
+        self.handle.write(buffer)
+    }
+
+    /// Write the whole buffer at the current position
+    pub fn write_all(&self, buffer: &[u8]) -> EFIResult<()> {
+        self.handle.write_all(buffer)
+    }
+
+    /// Flush the writes to the device
+    pub fn flush(&self) -> EFIResult<()> {
+        self.handle.flush()
+    }
+
+    /// Get the current position in the file
+    pub fn get_position(&self) -> EFIResult<u64> {
+        self.handle.get_position()
+    }
+
+    /// Set the current position in the file
+    pub fn set_position(&self, position: u64) -> EFIResult<()> {
+        self.handle.set_position(position)
+    }
+
+    /// Move the current position to the end of the file i.e to append to
+    /// the file
+    pub fn seek_to_end(&self) -> EFIResult<()> {
+        self.handle.seek_to_end()
+    }
+
+    /// Change the size of the file, the file is truncated or grown
+    /// with zeros
+    pub fn set_size(&self, size: u64) -> EFIResult<()> {
+        self.handle.set_size(size)
+    }
+
+    /// Get the file info
+    pub fn get_info(&self) -> EFIResult<EFIFileInfo> {
+        self.handle.get_info()
+    }
+
+    /// Close the file and report if the writes couldn't be flushed,
+    /// dropping the file ignores the error
+    pub fn close(self) -> EFIResult<()> {
+        let handle = self.handle;
+        core::mem::forget(self);
+
+        unsafe { handle.close() }
+    }
+
+    /// Delete the file, the file is closed even if it couldn't be deleted
+    pub fn delete(self) -> EFIResult<()> {
+        let handle = self.handle;
+        core::mem::forget(self);
+
+        unsafe { handle.delete() }
+    }
+}
+
+impl<'a> Drop for File<'a> {
+    fn drop(&mut self) {
+        // NOTE(patrik): Nothing we can do if the close fails
+        unsafe {
+            let _ = self.handle.close();
+        }
+    }
+}
+
+/// A opened directory, the handle is closed when the directory is
+/// dropped. The lifetime is tied to the filesystem the directory was
+/// opened from so the files opened from the directory can outlive it
+pub struct Directory<'a> {
+    handle: &'a EFIFileHandle,
+}
+
+impl<'a> Directory<'a> {
+    /// Create a directory for a opened handle
+    fn new(handle: &'a EFIFileHandle) -> Self {
+        Self {
+            handle,
+        }
+    }
+
+    /// Open a file in the directory, fails if the filename is a directory
+    pub fn open_file(&self, filename: &str, open_mode: FileMode)
+        -> EFIResult<File<'a>>
+    {
+        open_checked(self.handle, filename, open_mode,
+                     FileAttribute::empty(), false)
+            .map(File::new)
+    }
+
+    /// Open a file for reading and writing, the file is created if it
+    /// doesn't exist
+    pub fn create_file(&self, filename: &str) -> EFIResult<File<'a>> {
+        open_checked(self.handle, filename,
+                     FileMode::READ | FileMode::WRITE | FileMode::CREATE,
+                     FileAttribute::empty(), false)
+            .map(File::new)
+    }
+
+    /// Open a directory in the directory, fails if the filename is a file
+    pub fn open_directory(&self, filename: &str)
+        -> EFIResult<Directory<'a>>
+    {
+        open_checked(self.handle, filename, FileMode::READ,
+                     FileAttribute::empty(), true)
+            .map(Directory::new)
+    }
+
+    /// Open a directory in the directory, the directory is created if it
+    /// doesn't exist
+    pub fn create_directory(&self, filename: &str)
+        -> EFIResult<Directory<'a>>
+    {
+        open_checked(self.handle, filename,
+                     FileMode::READ | FileMode::WRITE | FileMode::CREATE,
+                     FileAttribute::DIRECTORY, true)
+            .map(Directory::new)
+    }
+
+    /// Iterate over the entries in the directory, the iterator starts
+    /// from the first entry and skips the '.' and '..' entries
+    pub fn read_dir(&self) -> EFIResult<ReadDir<'_>> {
+        self.handle.read_dir()
+    }
+
+    /// Get the info of the directory
+    pub fn get_info(&self) -> EFIResult<EFIFileInfo> {
+        self.handle.get_info()
+    }
+
+    /// Close the directory and report if the close failed, dropping the
+    /// directory ignores the error
+    pub fn close(self) -> EFIResult<()> {
+        let handle = self.handle;
+        core::mem::forget(self);
+
+        unsafe { handle.close() }
+    }
+
+    /// Delete the directory, the directory is closed even if it couldn't
+    /// be deleted
+    pub fn delete(self) -> EFIResult<()> {
+        let handle = self.handle;
+        core::mem::forget(self);
+
+        unsafe { handle.delete() }
+    }
+}
+
+impl<'a> Drop for Directory<'a> {
+    fn drop(&mut self) {
+        // NOTE(patrik): Nothing we can do if the close fails
+        unsafe {
+            let _ = self.handle.close();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build the buffer the firmware gives us for a file
+    fn info_buffer(name: &str, file_size: u64) -> Vec<u8> {
+        let info_size = core::mem::size_of::<EFIFileInfo>();
+        let name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+
+        let info = EFIFileInfo {
+            size: (info_size + name.len() * 2) as u64,
+            file_size,
+            physical_size: 4096,
+            create_time: EFITime::default(),
+            last_access_time: EFITime::default(),
+            modification_time: EFITime::default(),
+            attribute: FileAttribute::DIRECTORY.bits(),
+        };
+
+        let mut buffer = vec![0u8; info_size];
+        unsafe {
+            core::ptr::write_unaligned(buffer.as_mut_ptr() as *mut _, info);
+        }
+        buffer.extend(name.iter().flat_map(|c| c.to_le_bytes()));
+
+        buffer
+    }
+
+    #[test]
+    fn entry_from_info_buffer() {
+        let buffer = info_buffer("kernel.elf", 1234);
+        let entry = DirEntry::from_info_buffer(&buffer).unwrap();
+
+        assert_eq!(entry.name, "kernel.elf");
+        assert_eq!(entry.file_size, 1234);
+        assert!(entry.is_directory());
+    }
+
+    #[test]
+    fn short_info_buffers_are_rejected() {
+        let buffer = info_buffer("kernel.elf", 1234);
+        let info_size = core::mem::size_of::<EFIFileInfo>();
+
+        // Shorter than the struct
+        let err = DirEntry::from_info_buffer(&buffer[..info_size - 1])
+            .unwrap_err();
+        assert_eq!(err.status(), EFIStatus::CompromisedData);
+
+        // The entry says it's bigger than the buffer
+        let err = DirEntry::from_info_buffer(&buffer[..buffer.len() - 2])
+            .unwrap_err();
+        assert_eq!(err.status(), EFIStatus::CompromisedData);
     }
 }
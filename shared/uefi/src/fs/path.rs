@@ -0,0 +1,222 @@
+use alloc::string::{ String, ToString };
+use alloc::vec::Vec;
+
+/// The separator the firmware uses in paths
+pub const SEPARATOR: char = '\\';
+
+/// Check if a character separates the components of a path, both '/'
+/// and '\' are accepted so the paths in the options can use either
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/// A normalized path on a filesystem, the '.' components are removed
+/// and the '..' components removes the component before it. Relative
+/// paths keep the '..' components that go above the start of the path
+#[derive(PartialEq, Eq, Clone, Default, Debug)]
+pub struct Path {
+    absolute: bool,
+    components: Vec<String>,
+}
+
+impl Path {
+    /// Parse a path with '/' or '\' as the separator
+    pub fn new(path: &str) -> Self {
+        let mut result = Self {
+            absolute: path.starts_with(is_separator),
+            components: Vec::new(),
+        };
+
+        for component in path.split(is_separator) {
+            result.push(component);
+        }
+
+        result
+    }
+
+    /// The root of the filesystem
+    pub fn root() -> Self {
+        Self {
+            absolute: true,
+            components: Vec::new(),
+        }
+    }
+
+    /// Add a component to the end of the path
+    fn push(&mut self, component: &str) {
+        match component {
+            "" | "." => {}
+
+            ".." => {
+                // A '..' after a '..' or at the start of a relative path
+                // can't be resolved so it's kept, the root is its own
+                // parent
+                match self.components.last().map(|c| c.as_str()) {
+                    Some("..") | None if !self.absolute => {
+                        self.components.push(component.to_string());
+                    }
+                    _ => {
+                        self.components.pop();
+                    }
+                }
+            }
+
+            _ => self.components.push(component.to_string()),
+        }
+    }
+
+    /// Join a path to the end of this path, if `path` is absolute the
+    /// result is `path`
+    pub fn join(&self, path: &str) -> Self {
+        if path.starts_with(is_separator) {
+            return Self::new(path);
+        }
+
+        let mut result = self.clone();
+        for component in path.split(is_separator) {
+            result.push(component);
+        }
+
+        result
+    }
+
+    /// Check if the path starts at the root of the filesystem
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    /// The path without the last component, None if the path is the
+    /// root or empty
+    pub fn parent(&self) -> Option<Self> {
+        if self.components.is_empty() {
+            return None;
+        }
+
+        let mut result = self.clone();
+        result.components.pop();
+
+        Some(result)
+    }
+
+    /// The last component of the path
+    pub fn file_name(&self) -> Option<&str> {
+        self.components.last()
+            .map(|c| c.as_str())
+            .filter(|&c| c != "..")
+    }
+
+    /// Iterate over the components of the path
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.components.iter().map(|c| c.as_str())
+    }
+}
+
+impl core::fmt::Display for Path {
+    /// Format the path with the separator the firmware uses
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        use core::fmt::Write;
+
+        if self.absolute {
+            f.write_char(SEPARATOR)?;
+        }
+
+        for (index, component) in self.components.iter().enumerate() {
+            if index > 0 {
+                f.write_char(SEPARATOR)?;
+            }
+
+            f.write_str(component)?;
+        }
+
+        // NOTE(patrik): A empty relative path is the current directory
+        if !self.absolute && self.components.is_empty() {
+            f.write_char('.')?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&str> for Path {
+    fn from(path: &str) -> Self {
+        Self::new(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> String {
+        Path::new(path).to_string()
+    }
+
+    #[test]
+    fn dots_are_resolved() {
+        assert_eq!(path("/EFI/./boot/../boot/kernel.elf"),
+                   "\\EFI\\boot\\kernel.elf");
+        assert_eq!(path("a/b/../../c"), "c");
+        assert_eq!(path("./a/."), "a");
+        assert_eq!(path("a/.."), ".");
+    }
+
+    #[test]
+    fn both_separators_are_accepted() {
+        assert_eq!(path("/EFI\\boot/kernel.elf"), "\\EFI\\boot\\kernel.elf");
+        assert_eq!(path("\\EFI//boot\\\\"), "\\EFI\\boot");
+        assert_eq!(Path::new("a/b"), Path::new("a\\b"));
+    }
+
+    #[test]
+    fn dot_dot_above_the_start() {
+        // The root is its own parent
+        assert_eq!(path("/../.."), "\\");
+        assert_eq!(path("/../EFI"), "\\EFI");
+
+        // Relative paths keep the components they can't resolve
+        assert_eq!(path("../a"), "..\\a");
+        assert_eq!(path("a/../../b"), "..\\b");
+        assert_eq!(path("../../a"), "..\\..\\a");
+    }
+
+    #[test]
+    fn join() {
+        let directory = Path::new("/EFI/boot");
+
+        assert_eq!(directory.join("kernel.elf").to_string(),
+                   "\\EFI\\boot\\kernel.elf");
+        assert_eq!(directory.join("../fonts/font.psf").to_string(),
+                   "\\EFI\\fonts\\font.psf");
+
+        // A absolute path replaces the directory
+        assert_eq!(directory.join("/kernel.elf").to_string(), "\\kernel.elf");
+        assert_eq!(directory.join("\\kernel.elf").to_string(),
+                   "\\kernel.elf");
+
+        // Joining a relative directory
+        assert_eq!(Path::new("config").join("../kernel.elf").to_string(),
+                   "kernel.elf");
+    }
+
+    #[test]
+    fn parent() {
+        assert_eq!(Path::new("/EFI/boot/options.txt").parent(),
+                   Some(Path::new("/EFI/boot")));
+        assert_eq!(Path::new("/EFI").parent(), Some(Path::root()));
+        assert_eq!(Path::new("options.txt").parent(), Some(Path::new("")));
+
+        assert_eq!(Path::root().parent(), None);
+        assert_eq!(Path::new("/..").parent(), None);
+        assert_eq!(Path::new("").parent(), None);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Path::root().to_string(), "\\");
+        assert_eq!(Path::default().to_string(), ".");
+        assert!(Path::new("/a").is_absolute());
+        assert!(!Path::new("a").is_absolute());
+        assert_eq!(Path::new("/a/b").file_name(), Some("b"));
+        assert_eq!(Path::new("..").file_name(), None);
+    }
+}
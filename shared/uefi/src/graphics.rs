@@ -1,4 +1,6 @@
-use crate::{ EFIGuid, PhysicalAddress };
+use crate::{ EFIGuid, EFIStatus, EFIError, EFIResult };
+use crate::{ PhysicalAddress, Protocol };
+use crate::BootServices;
 
 // GUID for the GraphicsOutputProtocol (GOP)
 pub const GRAPHICS_OUTPUT_PROTOCOL_GUID: EFIGuid =
@@ -25,25 +27,126 @@ pub enum EFIGraphicsPixelFormat {
 #[derive(Copy, Clone, Debug)]
 #[repr(C)]
 pub struct EFIGraphicsPixelInfomation {
-    red_mask: u32,
-    green_mask: u32,
-    blue_mask: u32,
-    reserved_mask: u32,
+    pub red_mask: u32,
+    pub green_mask: u32,
+    pub blue_mask: u32,
+    pub reserved_mask: u32,
 }
 
 /// Infomation about the framebuffer
 /// i.e the width, the height, how the pixels should be encoded and more
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct EFIGraphicsOutputInfo {
     version: u32,
     pub width: u32,
     pub height: u32,
-    pixel_format: EFIGraphicsPixelFormat,
-    pixel_infomation: EFIGraphicsPixelInfomation,
+    pub pixel_format: EFIGraphicsPixelFormat,
+    /// The masks for the color channels, only valid if the pixel format
+    /// is `PixelBitMask`
+    pub pixel_infomation: EFIGraphicsPixelInfomation,
     pub pixels_per_scanline: u32,
 }
 
+/// A pixel used by the blt operations, the layout is the same for
+/// every mode even if the framebuffer uses a diffrent pixel format
+#[derive(PartialEq, Eq, Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct BltPixel {
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+    pub reserved: u8,
+}
+
+impl BltPixel {
+    /// Create a pixel from the color channels
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            blue,
+            green,
+            red,
+            reserved: 0,
+        }
+    }
+}
+
+/// The operation the firmware should do in `blt`
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(C)]
+enum EFIBltOperation {
+    VideoFill,
+    VideoToBltBuffer,
+    BufferToVideo,
+    VideoToVideo,
+}
+
+/// A blt operation for `EFIGraphicsOutputProtocol::blt`, the positions
+/// and sizes are in pixels as (x, y) and (width, height) and the stride
+/// is the number of pixels in a row of the buffer
+#[derive(Debug)]
+pub enum BltOperation<'b> {
+    /// Fill a rectangle on the screen with a color
+    VideoFill {
+        color: BltPixel,
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+
+    /// Copy a rectangle on the screen to the buffer
+    VideoToBltBuffer {
+        buffer: &'b mut [BltPixel],
+        stride: usize,
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+
+    /// Copy a rectangle in the buffer to the screen
+    BufferToVideo {
+        buffer: &'b [BltPixel],
+        stride: usize,
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+
+    /// Copy a rectangle on the screen to another place on the screen
+    VideoToVideo {
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+}
+
+/// Check that the rectangle at `pos` with `size` is inside of a buffer
+/// with `len` pixels and `stride` pixels in each row
+fn check_blt_buffer(len: usize, stride: usize,
+                    pos: (usize, usize), size: (usize, usize))
+    -> EFIResult<()>
+{
+    let (x, y) = pos;
+    let (width, height) = size;
+
+    // An empty rectangle doesn't touch the buffer
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let end = x.checked_add(width)
+        .filter(|&end| end <= stride)
+        .and_then(|end| {
+            let last_row = y.checked_add(height - 1)?;
+            last_row.checked_mul(stride)?.checked_add(end)
+        });
+
+    match end {
+        Some(end) if end <= len => Ok(()),
+        _ => Err(EFIError::new(EFIStatus::InvalidParameter,
+                               "EFIGraphicsOutputProtocol::blt")),
+    }
+}
+
 /// The mode the GOP is in, it contains the framebuffer base address
 /// and infomation about the framebuffer
 #[repr(C)]
@@ -56,12 +159,200 @@ pub struct EFIGraphicsOutputMode<'a> {
     pub framebuffer_size: u64,
 }
 
+impl<'a> EFIGraphicsOutputMode<'a> {
+    /// The number of modes the output supports, the valid modes are
+    /// from 0 to `max_mode - 1`
+    pub fn max_mode(&self) -> u32 {
+        self.max_mode
+    }
+
+    /// The mode the output is currently in
+    pub fn current_mode(&self) -> u32 {
+        self.mode
+    }
+}
+
+/// Function pointer for the blt slot in the GraphicsOutputProtocol
+type EFIBltFn = unsafe fn(&EFIGraphicsOutputProtocol,
+                          blt_buffer: *mut BltPixel,
+                          blt_operation: EFIBltOperation,
+                          source_x: usize,
+                          source_y: usize,
+                          destination_x: usize,
+                          destination_y: usize,
+                          width: usize,
+                          height: usize,
+                          delta: usize) -> EFIStatus;
+
 /// The GraphicsOutputProtocol handle have function pointers to
 /// muniplulate the framebuffer and ways to get the current framebuffer
 #[repr(C)]
 pub struct EFIGraphicsOutputProtocol<'a> {
-    query_mode: usize,
-    set_mode: usize,
-    blt: usize,
+    query_mode_fn: unsafe fn(&EFIGraphicsOutputProtocol,
+                             mode_number: u32,
+                             size_of_info: &mut usize,
+                             info: &mut *mut EFIGraphicsOutputInfo)
+        -> EFIStatus,
+    set_mode_fn: unsafe fn(&EFIGraphicsOutputProtocol,
+                           mode_number: u32) -> EFIStatus,
+    blt_fn: EFIBltFn,
     pub mode: &'a EFIGraphicsOutputMode<'a>,
 }
+
+unsafe impl<'a> Protocol for EFIGraphicsOutputProtocol<'a> {
+    const GUID: EFIGuid = GRAPHICS_OUTPUT_PROTOCOL_GUID;
+}
+
+impl<'a> EFIGraphicsOutputProtocol<'a> {
+    /// Get the infomation about a mode, the firmware allocates the
+    /// infomation so we need the boot services to free it
+    pub fn query_mode(&self, boot_services: &BootServices, mode_number: u32)
+        -> EFIResult<EFIGraphicsOutputInfo>
+    {
+        let mut size_of_info = 0;
+        let mut info = core::ptr::null_mut();
+
+        let status = unsafe {
+            (self.query_mode_fn)(self, mode_number,
+                                 &mut size_of_info, &mut info)
+        };
+
+        status.into_result("EFIGraphicsOutputProtocol::query_mode")?;
+
+        // Copy the infomation out of the firmware buffer and free it
+        // NOTE(patrik): The buffer can be bigger than our struct if the
+        // firmware uses a newer version of the struct
+        unsafe {
+            if size_of_info < core::mem::size_of::<EFIGraphicsOutputInfo>() {
+                boot_services.free_pool(info as *mut u8)?;
+                return Err(EFIError::new(EFIStatus::BadBufferSize,
+                               "EFIGraphicsOutputProtocol::query_mode"));
+            }
+
+            let result = core::ptr::read_unaligned(info);
+            boot_services.free_pool(info as *mut u8)?;
+
+            Ok(result)
+        }
+    }
+
+    /// Iterate over all the modes the output supports with the mode
+    /// number and the infomation about the mode
+    pub fn modes<'b>(&'b self, boot_services: &'b BootServices)
+        -> impl Iterator<Item = (u32, EFIResult<EFIGraphicsOutputInfo>)> + 'b
+    {
+        (0..self.mode.max_mode).map(move |mode_number| {
+            (mode_number, self.query_mode(boot_services, mode_number))
+        })
+    }
+
+    /// Change the mode of the output, this clears the screen and
+    /// changes the framebuffer in `mode`
+    pub fn set_mode(&self, mode_number: u32) -> EFIResult<()> {
+        let status = unsafe {
+            (self.set_mode_fn)(self, mode_number)
+        };
+
+        status.into_result("EFIGraphicsOutputProtocol::set_mode")
+    }
+
+    /// Do a blt operation, the buffers are checked to make sure the
+    /// rectangles are inside of them. This works on modes without a
+    /// linear framebuffer i.e `PixelBltOnly`
+    pub fn blt(&self, operation: BltOperation) -> EFIResult<()> {
+        let pixel_size = core::mem::size_of::<BltPixel>();
+
+        let status = match operation {
+            BltOperation::VideoFill { color, dest, size } => {
+                // The firmware only reads the first pixel of the buffer
+                let mut color = color;
+
+                unsafe {
+                    (self.blt_fn)(self, &mut color,
+                                  EFIBltOperation::VideoFill,
+                                  0, 0, dest.0, dest.1, size.0, size.1, 0)
+                }
+            }
+
+            BltOperation::VideoToBltBuffer {
+                buffer, stride, src, dest, size
+            } => {
+                check_blt_buffer(buffer.len(), stride, dest, size)?;
+
+                unsafe {
+                    (self.blt_fn)(self, buffer.as_mut_ptr(),
+                                  EFIBltOperation::VideoToBltBuffer,
+                                  src.0, src.1, dest.0, dest.1,
+                                  size.0, size.1, stride * pixel_size)
+                }
+            }
+
+            BltOperation::BufferToVideo { buffer, stride, src, dest, size } => {
+                check_blt_buffer(buffer.len(), stride, src, size)?;
+
+                // NOTE(patrik): The firmware only reads from the buffer
+                // for this operation so the cast to mut is fine
+                unsafe {
+                    (self.blt_fn)(self, buffer.as_ptr() as *mut BltPixel,
+                                  EFIBltOperation::BufferToVideo,
+                                  src.0, src.1, dest.0, dest.1,
+                                  size.0, size.1, stride * pixel_size)
+                }
+            }
+
+            BltOperation::VideoToVideo { src, dest, size } => {
+                unsafe {
+                    (self.blt_fn)(self, core::ptr::null_mut(),
+                                  EFIBltOperation::VideoToVideo,
+                                  src.0, src.1, dest.0, dest.1,
+                                  size.0, size.1, 0)
+                }
+            }
+        };
+
+        status.into_result("EFIGraphicsOutputProtocol::blt")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blt_buffer_exact_fit() {
+        // 4x3 rectangle in a 4 pixel wide buffer
+        assert!(check_blt_buffer(12, 4, (0, 0), (4, 3)).is_ok());
+
+        // The last pixel of a rectangle at an offset
+        assert!(check_blt_buffer(12, 4, (1, 1), (3, 2)).is_ok());
+        assert!(check_blt_buffer(12, 4, (3, 2), (1, 1)).is_ok());
+    }
+
+    #[test]
+    fn blt_buffer_one_pixel_short() {
+        assert!(check_blt_buffer(11, 4, (0, 0), (4, 3)).is_err());
+        assert!(check_blt_buffer(11, 4, (3, 2), (1, 1)).is_err());
+    }
+
+    #[test]
+    fn blt_buffer_rows_wider_than_the_stride() {
+        assert!(check_blt_buffer(100, 4, (0, 0), (5, 1)).is_err());
+        assert!(check_blt_buffer(100, 4, (1, 0), (4, 1)).is_err());
+    }
+
+    #[test]
+    fn blt_buffer_overflow() {
+        assert!(check_blt_buffer(usize::MAX, usize::MAX,
+                                 (usize::MAX, 0), (1, 1)).is_err());
+        assert!(check_blt_buffer(usize::MAX, 4,
+                                 (0, usize::MAX), (1, 2)).is_err());
+        assert!(check_blt_buffer(usize::MAX, usize::MAX / 2,
+                                 (0, 3), (1, 1)).is_err());
+    }
+
+    #[test]
+    fn blt_buffer_empty_rectangle() {
+        assert!(check_blt_buffer(0, 0, (100, 100), (0, 5)).is_ok());
+        assert!(check_blt_buffer(0, 0, (100, 100), (5, 0)).is_ok());
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{ EFIStatus, EFIDevicePathProtocol };
+
+use alloc::string::String;
+
+/// Where `BootServices::load_image` should load the image from
+#[derive(Clone, Copy, Debug)]
+pub enum LoadImageSource<'b> {
+    /// Load the image from a file, the firmware finds the device and
+    /// reads the file itself
+    FromPath(&'b EFIDevicePathProtocol),
+
+    /// Load the image from a buffer we have read, the path is given to
+    /// the image in the LoadedImage protocol so it knows where it was
+    /// loaded from
+    FromBuffer {
+        buffer: &'b [u8],
+        file_path: Option<&'b EFIDevicePathProtocol>,
+    },
+}
+
+/// The result of a image started with `BootServices::start_image`
+#[derive(Clone, Debug)]
+pub struct ImageExit {
+    /// The status the image exited with, or the error if the image
+    /// couldn't be started. The image can give us any value so it's not
+    /// a `EFIStatus`
+    pub raw_status: u64,
+
+    /// The string the image exited with, images usually only give us
+    /// a string when they fail
+    pub exit_data: Option<String>,
+}
+
+impl ImageExit {
+    /// The status the image exited with, None if the image exited with
+    /// a status we don't know about i.e a vendor status
+    pub fn status(&self) -> Option<EFIStatus> {
+        EFIStatus::from_raw(self.raw_status)
+    }
+}
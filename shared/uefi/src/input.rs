@@ -0,0 +1,245 @@
+use crate::{ EFIStatus, EFIGuid, EFIResult, Protocol };
+use crate::event::EFIEvent;
+
+/// GUID for the SimpleTextInput protocol
+pub const SIMPLE_TEXT_INPUT_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0x387477c1,
+        data2: 0x69c7,
+        data3: 0x11d2,
+        data4: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]
+    };
+
+/// GUID for the SimpleTextInputEx protocol
+pub const SIMPLE_TEXT_INPUT_EX_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0xdd9e7534,
+        data2: 0x7762,
+        data3: 0x4698,
+        data4: [0x8c, 0x14, 0xf5, 0x85, 0x17, 0xa6, 0x25, 0xaa]
+    };
+
+// Scan codes for the keys that don't have a unicode character
+pub const SCAN_NULL:      u16 = 0x0000;
+pub const SCAN_UP:        u16 = 0x0001;
+pub const SCAN_DOWN:      u16 = 0x0002;
+pub const SCAN_RIGHT:     u16 = 0x0003;
+pub const SCAN_LEFT:      u16 = 0x0004;
+pub const SCAN_HOME:      u16 = 0x0005;
+pub const SCAN_END:       u16 = 0x0006;
+pub const SCAN_INSERT:    u16 = 0x0007;
+pub const SCAN_DELETE:    u16 = 0x0008;
+pub const SCAN_PAGE_UP:   u16 = 0x0009;
+pub const SCAN_PAGE_DOWN: u16 = 0x000a;
+pub const SCAN_F1:        u16 = 0x000b;
+pub const SCAN_F2:        u16 = 0x000c;
+pub const SCAN_F3:        u16 = 0x000d;
+pub const SCAN_F4:        u16 = 0x000e;
+pub const SCAN_F5:        u16 = 0x000f;
+pub const SCAN_F6:        u16 = 0x0010;
+pub const SCAN_F7:        u16 = 0x0011;
+pub const SCAN_F8:        u16 = 0x0012;
+pub const SCAN_F9:        u16 = 0x0013;
+pub const SCAN_F10:       u16 = 0x0014;
+pub const SCAN_ESC:       u16 = 0x0017;
+
+// Flags for the shift keys that was held down when a key was pressed
+bitflags! {
+    pub struct EFIKeyShiftState: u32 {
+        const SHIFT_STATE_VALID = 0x80000000;
+        const RIGHT_SHIFT       = 0x00000001;
+        const LEFT_SHIFT        = 0x00000002;
+        const RIGHT_CONTROL     = 0x00000004;
+        const LEFT_CONTROL      = 0x00000008;
+        const RIGHT_ALT         = 0x00000010;
+        const LEFT_ALT          = 0x00000020;
+        const RIGHT_LOGO        = 0x00000040;
+        const LEFT_LOGO         = 0x00000080;
+        const MENU_KEY          = 0x00000100;
+        const SYS_REQ           = 0x00000200;
+    }
+}
+
+// Flags for the toggle keys i.e caps lock
+bitflags! {
+    pub struct EFIKeyToggleState: u8 {
+        const TOGGLE_STATE_VALID = 0x80;
+        const KEY_STATE_EXPOSED  = 0x40;
+        const SCROLL_LOCK        = 0x01;
+        const NUM_LOCK           = 0x02;
+        const CAPS_LOCK          = 0x04;
+    }
+}
+
+/// A key read from a input device, keys without a unicode character
+/// i.e the arrow keys only have a scan code
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EFIInputKey {
+    pub scan_code: u16,
+    pub unicode_char: u16,
+}
+
+impl EFIInputKey {
+    /// Get the character of the key if the key have one
+    pub fn char(&self) -> Option<char> {
+        if self.unicode_char == 0 {
+            None
+        } else {
+            core::char::from_u32(self.unicode_char as u32)
+        }
+    }
+}
+
+/// The state of the shift and toggle keys when a key was pressed
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EFIKeyState {
+    key_shift_state: u32,
+    key_toggle_state: u8,
+}
+
+impl EFIKeyState {
+    /// The shift keys that was held down
+    pub fn shift_state(&self) -> EFIKeyShiftState {
+        EFIKeyShiftState::from_bits_truncate(self.key_shift_state)
+    }
+
+    /// The toggle keys that was active
+    pub fn toggle_state(&self) -> EFIKeyToggleState {
+        EFIKeyToggleState::from_bits_truncate(self.key_toggle_state)
+    }
+}
+
+/// A key read from the extended input protocol
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct EFIKeyData {
+    pub key: EFIInputKey,
+    pub key_state: EFIKeyState,
+}
+
+/// A interface to read keys from a input device like the keyboard
+#[repr(C)]
+pub struct SimpleTextInputInterface {
+    reset_fn: unsafe fn(&SimpleTextInputInterface,
+                        extended_verification: bool) -> EFIStatus,
+    read_key_stroke_fn: unsafe fn(&SimpleTextInputInterface,
+                                  key: &mut EFIInputKey) -> EFIStatus,
+    wait_for_key: EFIEvent,
+}
+
+unsafe impl Protocol for SimpleTextInputInterface {
+    const GUID: EFIGuid = SIMPLE_TEXT_INPUT_GUID;
+}
+
+impl SimpleTextInputInterface {
+    /// Reset the input device and clear the pending keys
+    pub fn reset(&self, extended_verification: bool) -> EFIResult<()> {
+        let status = unsafe {
+            (self.reset_fn)(self, extended_verification)
+        };
+
+        status.into_result("SimpleTextInputInterface::reset")
+    }
+
+    /// Read the next key, returns None if no key have been pressed
+    pub fn read_key_stroke(&self) -> EFIResult<Option<EFIInputKey>> {
+        let mut key = EFIInputKey {
+            scan_code: 0,
+            unicode_char: 0,
+        };
+
+        let status = unsafe {
+            (self.read_key_stroke_fn)(self, &mut key)
+        };
+
+        // NotReady means that there is no key pending
+        if status == EFIStatus::NotReady {
+            return Ok(None);
+        }
+
+        status.into_result("SimpleTextInputInterface::read_key_stroke")?;
+
+        Ok(Some(key))
+    }
+
+    /// The event that is signaled when a key is pending
+    pub fn wait_for_key(&self) -> EFIEvent {
+        self.wait_for_key
+    }
+}
+
+/// The extended input interface, it can read the state of the shift
+/// and toggle keys
+#[repr(C)]
+pub struct EFISimpleTextInputExProtocol {
+    reset_fn: unsafe fn(&EFISimpleTextInputExProtocol,
+                        extended_verification: bool) -> EFIStatus,
+    read_key_stroke_ex_fn: unsafe fn(&EFISimpleTextInputExProtocol,
+                                     key_data: &mut EFIKeyData) -> EFIStatus,
+    wait_for_key_ex: EFIEvent,
+    set_state_fn: unsafe fn(&EFISimpleTextInputExProtocol,
+                            key_toggle_state: &u8) -> EFIStatus,
+    register_key_notify_fn: usize,
+    unregister_key_notify_fn: usize,
+}
+
+unsafe impl Protocol for EFISimpleTextInputExProtocol {
+    const GUID: EFIGuid = SIMPLE_TEXT_INPUT_EX_GUID;
+}
+
+impl EFISimpleTextInputExProtocol {
+    /// Reset the input device and clear the pending keys
+    pub fn reset(&self, extended_verification: bool) -> EFIResult<()> {
+        let status = unsafe {
+            (self.reset_fn)(self, extended_verification)
+        };
+
+        status.into_result("EFISimpleTextInputExProtocol::reset")
+    }
+
+    /// Read the next key with the state of the shift and toggle keys,
+    /// returns None if no key have been pressed
+    pub fn read_key_stroke(&self) -> EFIResult<Option<EFIKeyData>> {
+        let mut key_data = EFIKeyData {
+            key: EFIInputKey {
+                scan_code: 0,
+                unicode_char: 0,
+            },
+            key_state: EFIKeyState {
+                key_shift_state: 0,
+                key_toggle_state: 0,
+            },
+        };
+
+        let status = unsafe {
+            (self.read_key_stroke_ex_fn)(self, &mut key_data)
+        };
+
+        // NotReady means that there is no key pending
+        if status == EFIStatus::NotReady {
+            return Ok(None);
+        }
+
+        status.into_result("EFISimpleTextInputExProtocol::read_key_stroke")?;
+
+        Ok(Some(key_data))
+    }
+
+    /// Set the state of the toggle keys i.e turn on caps lock
+    pub fn set_state(&self, state: EFIKeyToggleState) -> EFIResult<()> {
+        let state = state.bits();
+
+        let status = unsafe {
+            (self.set_state_fn)(self, &state)
+        };
+
+        status.into_result("EFISimpleTextInputExProtocol::set_state")
+    }
+
+    /// The event that is signaled when a key is pending
+    pub fn wait_for_key(&self) -> EFIEvent {
+        self.wait_for_key_ex
+    }
+}
@@ -4,15 +4,41 @@
 pub mod graphics;
 pub mod fs;
 pub mod memory;
+pub mod error;
+pub mod protocol;
+pub mod input;
+pub mod event;
+pub mod runtime;
+pub mod variable;
+pub mod configuration;
+pub mod string;
+pub mod device_path;
+pub mod image;
 
 use crate::memory::{ EFIMemoryMap, EFIMemoryType, EFIAllocateType };
+use crate::memory::{ AllocateType, PhysRange, MemoryMap };
 use crate::memory::MemoryDescriptor;
+use crate::input::{ SimpleTextInputInterface, EFISimpleTextInputExProtocol };
+use crate::event::{ EFIEvent, EFIEventType, EFIEventNotify, EventGuard };
+use crate::event::{ EFITpl, EFITimerDelay };
+use crate::runtime::RuntimeServices;
+use crate::configuration::EFIConfigurationTable;
+use crate::string::CStr16;
+use crate::image::{ LoadImageSource, ImageExit };
+use crate::configuration::{ ACPI_TABLE_GUID, ACPI_20_TABLE_GUID };
+use crate::configuration::{ SMBIOS_TABLE_GUID, SMBIOS3_TABLE_GUID };
+
+pub use crate::error::{ EFIError, EFIResult };
+pub use crate::protocol::{ Protocol, ProtocolGuard, OpenProtocolAttributes };
+pub use crate::device_path::EFIDevicePathProtocol;
 
 /// External crates this library uses
 #[macro_use] extern crate bitflags;
 #[macro_use] extern crate alloc;
 
 use core::ffi::c_void;
+use alloc::vec::Vec;
+use alloc::string::String;
 
 /// Declare a EFIHandle type that should be a pointer size
 pub type EFIHandle = usize;
@@ -29,7 +55,7 @@ pub struct VirtualAddress(pub u64);
 
 /// EFIStatus used for most of the UEFI API calls to retrive a status,
 /// and this enum has most of the warnings and errors that UEFI can report
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u64)]
 #[allow(dead_code)]
 pub enum EFIStatus {
@@ -73,7 +99,95 @@ pub enum EFIStatus {
     CompromisedData     = 0x8000000000000000 | 33,
 }
 
+impl EFIStatus {
+    /// The high bit of the status is set for all the error codes
+    const ERROR_BIT: u64 = 0x8000000000000000;
+
+    /// All the statuses we know about
+    const ALL: [EFIStatus; 36] = [
+        EFIStatus::Success,
+        EFIStatus::WarnUnknownGlyph,
+        EFIStatus::WarnDeleteFailure,
+        EFIStatus::WarnWriteFailure,
+        EFIStatus::WarnBufferToSmall,
+        EFIStatus::LoadError,
+        EFIStatus::InvalidParameter,
+        EFIStatus::Unsupported,
+        EFIStatus::BadBufferSize,
+        EFIStatus::BufferTooSmall,
+        EFIStatus::NotReady,
+        EFIStatus::DeviceError,
+        EFIStatus::WriteProtected,
+        EFIStatus::OutOfResources,
+        EFIStatus::VolumeCorrupted,
+        EFIStatus::VolumeFull,
+        EFIStatus::NoMedia,
+        EFIStatus::MediaChanged,
+        EFIStatus::NotFound,
+        EFIStatus::AccessDenied,
+        EFIStatus::NoResponse,
+        EFIStatus::NoMapping,
+        EFIStatus::Timeout,
+        EFIStatus::NotStarted,
+        EFIStatus::AlreadyStarted,
+        EFIStatus::Aborted,
+        EFIStatus::ICMPError,
+        EFIStatus::TFTPError,
+        EFIStatus::ProtocolError,
+        EFIStatus::IncompatibleVersion,
+        EFIStatus::SecurityViolation,
+        EFIStatus::CRCError,
+        EFIStatus::EndOfMedia,
+        EFIStatus::EndOfFile,
+        EFIStatus::InvalidLanguage,
+        EFIStatus::CompromisedData,
+    ];
+
+    /// Convert a status we got from code we don't control i.e the exit
+    /// status of a image, None if it isn't a status we know about
+    pub fn from_raw(status: u64) -> Option<EFIStatus> {
+        Self::ALL.iter().copied().find(|&known| known as u64 == status)
+    }
+
+    /// Check if the status is a success
+    pub fn is_success(self) -> bool {
+        self == EFIStatus::Success
+    }
+
+    /// Check if the status is one of the warning codes
+    pub fn is_warning(self) -> bool {
+        !self.is_success() && !self.is_error()
+    }
+
+    /// Check if the status is one of the error codes
+    pub fn is_error(self) -> bool {
+        (self as u64) & Self::ERROR_BIT != 0
+    }
+
+    /// Convert the status to a result, warnings are reported as errors
+    /// so the caller can decide if the warning matters or not
+    pub fn into_result(self, operation: &'static str) -> EFIResult<()> {
+        if self.is_success() {
+            Ok(())
+        } else {
+            Err(EFIError::new(self, operation))
+        }
+    }
+
+    /// Convert the status to a result but treat the warnings as a success
+    pub fn into_result_ignore_warnings(self, operation: &'static str)
+        -> EFIResult<()>
+    {
+        if self.is_error() {
+            Err(EFIError::new(self, operation))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 // Represents a UEFI Guid used for protocols mostly
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[repr(C)]
 pub struct EFIGuid {
     data1: u32,
@@ -82,17 +196,39 @@ pub struct EFIGuid {
     data4: [u8; 8],
 }
 
-/// GUID for the LoadedImage Protocol
-pub const LOADED_IMAGE_GUID: EFIGuid = EFIGuid { data1: 0x5B1B31A1, data2: 0x9562, data3: 0x11d2, data4: [0x8E, 0x3F, 0x00, 0xA0, 0xC9, 0x69, 0x72, 0x3B] };
+impl EFIGuid {
+    /// Create a guid from the parts in the registry format
+    pub const fn new(data1: u32, data2: u16, data3: u16, data4: [u8; 8])
+        -> Self
+    {
+        Self {
+            data1,
+            data2,
+            data3,
+            data4,
+        }
+    }
+}
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct EFIDevicePathProtocol {
-    typ: u8,
-    sub_typ: u8,
-    length: [u8; 2],
+impl core::fmt::Display for EFIGuid {
+    /// Format the guid in the registry format i.e
+    /// 5b1b31a1-9562-11d2-8e3f-00a0c969723b
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+               self.data1, self.data2, self.data3,
+               self.data4[0], self.data4[1])?;
+
+        for byte in &self.data4[2..] {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
 }
 
+/// GUID for the LoadedImage Protocol
+pub const LOADED_IMAGE_GUID: EFIGuid = EFIGuid { data1: 0x5B1B31A1, data2: 0x9562, data3: 0x11d2, data4: [0x8E, 0x3F, 0x00, 0xA0, 0xC9, 0x69, 0x72, 0x3B] };
+
 /// LoadedImage protocol has functions to get infomation about a image
 /// TODO(patrik): Wrappers and remove pub
 #[repr(C)]
@@ -105,8 +241,8 @@ pub struct EFILoadedImageProtocol<'a> {
     pub file_path: &'a EFIDevicePathProtocol,
     pub reserved: usize,
 
-    pub load_options_size: u32,
-    pub load_options: usize,
+    load_options_size: u32,
+    load_options: *const u8,
 
     pub image_base: usize,
     pub image_size: u64,
@@ -116,23 +252,74 @@ pub struct EFILoadedImageProtocol<'a> {
     unload_fn: usize,
 }
 
-/// A struct to represent time, used for mostly for files
+unsafe impl<'a> Protocol for EFILoadedImageProtocol<'a> {
+    const GUID: EFIGuid = LOADED_IMAGE_GUID;
+}
+
+impl<'a> EFILoadedImageProtocol<'a> {
+    /// The options the image was started with, the data is whatever
+    /// the one who started the image gave us
+    pub fn load_options(&self) -> &[u8] {
+        if self.load_options.is_null() {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(self.load_options,
+                                        self.load_options_size as usize)
+        }
+    }
+
+    /// The options decoded as a UCS-2 string, the UEFI shell and the
+    /// boot manager gives us the command line as a string. The string
+    /// ends at the first null character or at the end of the options
+    pub fn load_options_string(&self) -> Option<String> {
+        let options = self.load_options();
+
+        if options.is_empty() {
+            return None;
+        }
+
+        // NOTE(patrik): The options doesn't need to be aligned so the
+        // characters are read one byte at a time
+
+        let string = options.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .map(|c| {
+                core::char::from_u32(c as u32)
+                    .unwrap_or(core::char::REPLACEMENT_CHARACTER)
+            })
+            .collect();
+
+        Some(string)
+    }
+}
+
+/// A struct to represent time, used for files and the runtime clock
+/// NOTE(patrik): The timezone is the offset from UTC in minutes or
+/// `EFITime::UNSPECIFIED_TIMEZONE`
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Default, Debug)]
 pub struct EFITime {
-    year: u16,
-    month: u8,
-    day: u8,
-    hour: u8,
-    minute: u8,
-    second: u8,
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
     pad1: u8,
-    nano_second: u32,
-    timezone: u16,
-    daylight: u8,
+    pub nano_second: u32,
+    pub timezone: i16,
+    pub daylight: u8,
     pad2: u8,
 }
 
+impl EFITime {
+    /// Timezone value used when the time is local time
+    pub const UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+}
+
 // A interface to output text to a output interface like the console or serial
 #[repr(C)]
 pub struct SimpleTextOutputInterface {
@@ -141,7 +328,10 @@ pub struct SimpleTextOutputInterface {
     output_string_fn: unsafe fn(&SimpleTextOutputInterface, *const u16) -> EFIStatus,
     test_string_fn: usize,
 
-    quary_mode_fn: usize,
+    query_mode_fn: unsafe fn(&SimpleTextOutputInterface,
+                             mode_number: usize,
+                             columns: &mut usize,
+                             rows: &mut usize) -> EFIStatus,
     set_mode_fn: usize,
     set_attribute_fn: usize,
 
@@ -149,41 +339,81 @@ pub struct SimpleTextOutputInterface {
     set_cursor_position_fn: usize,
     enable_cursor_fn: usize,
 
-    mode_fn: usize,
+    mode: *const SimpleTextOutputMode,
+}
+
+/// The current state of a text output interface
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SimpleTextOutputMode {
+    pub max_mode: i32,
+    pub mode: i32,
+    pub attribute: i32,
+    pub cursor_column: i32,
+    pub cursor_row: i32,
+    pub cursor_visible: bool,
 }
 
 impl SimpleTextOutputInterface {
-    /// Output characters on the interface
-    pub fn output_string(&self, bytes: &[u16]) {
-        // Output the bytes to the interface
+    /// Output a string on the interface
+    /// NOTE(patrik): The warning for unknown glyphs is ignored because
+    /// the rest of the string is still displayed
+    pub fn output_string(&self, string: &CStr16) -> EFIResult<()> {
+        // Output the string to the interface
         let status = unsafe {
-            (self.output_string_fn)(self, bytes.as_ptr())
+            (self.output_string_fn)(self, string.as_ptr())
         };
 
         // Check the status for success
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove panic
-            panic!("Failed to output_string");
-        }
+        status.into_result_ignore_warnings(
+            "SimpleTextOutputInterface::output_string")
+    }
+
+    /// The current mode of the interface
+    pub fn mode(&self) -> &SimpleTextOutputMode {
+        unsafe { &*self.mode }
+    }
+
+    /// Get the size of a text mode as (columns, rows)
+    pub fn query_mode(&self, mode_number: usize)
+        -> EFIResult<(usize, usize)>
+    {
+        let mut columns = 0;
+        let mut rows = 0;
+
+        let status = unsafe {
+            (self.query_mode_fn)(self, mode_number, &mut columns, &mut rows)
+        };
+
+        status.into_result("SimpleTextOutputInterface::query_mode")?;
+
+        Ok((columns, rows))
     }
 
     /// Clear the screen
-    pub fn clear_screen(&self) {
+    pub fn clear_screen(&self) -> EFIResult<()> {
         // Issue the clear command
         let status = unsafe {
             (self.clear_screen_fn)(self)
         };
 
-        if status != EFIStatus::Success {
-            panic!("Failed to clear the screen");
-        }
+        status.into_result("SimpleTextOutputInterface::clear_screen")
     }
 }
 
-/// TableHeader for the SystemTable and BootServices
+/// The search types used when locating handles
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(C)]
+pub enum EFILocateSearchType {
+    AllHandles       = 0,
+    ByRegisterNotify = 1,
+    ByProtocol       = 2,
+}
+
+/// TableHeader for the SystemTable, BootServices and RuntimeServices
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
-struct TableHeader {
+pub(crate) struct TableHeader {
     signature: u64,
     revision: u32,
     header_size: u32,
@@ -202,18 +432,26 @@ pub struct BootServices {
 
     allocate_pages_fn: unsafe fn(EFIAllocateType, EFIMemoryType,
                                  u64, &mut u64) -> EFIStatus,
-    free_pages_fn: usize,
+    free_pages_fn: unsafe fn(memory: u64, pages: u64) -> EFIStatus,
     get_memory_map_fn: unsafe fn(&mut u64, *mut MemoryDescriptor,
                                  &mut u64, &mut u64, &mut u32) -> EFIStatus,
     allocate_pool_fn: unsafe fn(EFIMemoryType, u64, &mut *mut u8) -> EFIStatus,
     free_pool_fn: unsafe fn(*mut u8) -> EFIStatus,
 
-    create_event_fn: usize,
-    set_timer_fn: usize,
-    wait_for_event_fn: usize,
-    signal_event_fn: usize,
-    close_event_fn: usize,
-    check_event_fn: usize,
+    create_event_fn: unsafe fn(typ: u32,
+                               notify_tpl: EFITpl,
+                               notify_function: Option<EFIEventNotify>,
+                               notify_context: *mut c_void,
+                               event: &mut EFIEvent) -> EFIStatus,
+    set_timer_fn: unsafe fn(event: EFIEvent,
+                            typ: EFITimerDelay,
+                            trigger_time: u64) -> EFIStatus,
+    wait_for_event_fn: unsafe fn(number_of_events: usize,
+                                 event: *const EFIEvent,
+                                 index: &mut usize) -> EFIStatus,
+    signal_event_fn: unsafe fn(event: EFIEvent) -> EFIStatus,
+    close_event_fn: unsafe fn(event: EFIEvent) -> EFIStatus,
+    check_event_fn: unsafe fn(event: EFIEvent) -> EFIStatus,
 
     install_protocol_interface_fn: usize,
     reinstall_protocol_interface_fn: usize,
@@ -222,29 +460,60 @@ pub struct BootServices {
     handle_protocol_fn: unsafe fn(EFIHandle, &EFIGuid, &mut *mut c_void) -> EFIStatus,
     pc_handle_protocol_fn: usize,
     register_protocol_notify_fn: usize,
-    locate_handle_fn: usize,
+    locate_handle_fn: unsafe fn(search_type: EFILocateSearchType,
+                                protocol: *const EFIGuid,
+                                search_key: *const c_void,
+                                buffer_size: &mut usize,
+                                buffer: *mut EFIHandle) -> EFIStatus,
     locate_device_path_fn: usize,
     install_configuration_table_fn: usize,
 
-    load_image_fn: usize,
-    start_image_fn: usize,
-    exit_fn: usize,
-    unload_image_fn: usize,
+    load_image_fn: unsafe fn(boot_policy: bool,
+                             parent_image_handle: EFIHandle,
+                             device_path: *const EFIDevicePathProtocol,
+                             source_buffer: *const u8,
+                             source_size: usize,
+                             image_handle: &mut EFIHandle) -> EFIStatus,
+    start_image_fn: unsafe fn(image_handle: EFIHandle,
+                              exit_data_size: &mut usize,
+                              exit_data: &mut *mut u16) -> u64,
+    exit_fn: unsafe fn(image_handle: EFIHandle,
+                       exit_status: EFIStatus,
+                       exit_data_size: usize,
+                       exit_data: *const u16) -> EFIStatus,
+    unload_image_fn: unsafe fn(image_handle: EFIHandle) -> EFIStatus,
     exit_boot_services_fn: unsafe fn(EFIHandle, u64) -> EFIStatus,
 
-    get_next_monotonic_count_fn: usize,
-    stall_fn: usize,
-    set_watchdog_timer_fn: usize,
+    get_next_monotonic_count_fn: unsafe fn(count: &mut u64) -> EFIStatus,
+    stall_fn: unsafe fn(microseconds: usize) -> EFIStatus,
+    set_watchdog_timer_fn: unsafe fn(timeout: usize,
+                                     watchdog_code: u64,
+                                     data_size: usize,
+                                     watchdog_data: *const u16)
+                                -> EFIStatus,
 
     connect_controller_fn: usize,
     disconnect_controller_fn: usize,
 
-    open_protocol_fn: usize,
-    close_protocol_fn: usize,
+    open_protocol_fn: unsafe fn(handle: EFIHandle,
+                                protocol: &EFIGuid,
+                                interface: &mut *mut c_void,
+                                agent_handle: EFIHandle,
+                                controller_handle: EFIHandle,
+                                attributes: u32) -> EFIStatus,
+    close_protocol_fn: unsafe fn(handle: EFIHandle,
+                                 protocol: &EFIGuid,
+                                 agent_handle: EFIHandle,
+                                 controller_handle: EFIHandle) -> EFIStatus,
     open_protocol_infomation_fn: usize,
 
     protocols_per_handle_fn: usize,
-    locate_handle_buffer_fn: usize,
+    locate_handle_buffer_fn: unsafe fn(search_type: EFILocateSearchType,
+                                       protocol: *const EFIGuid,
+                                       search_key: *const c_void,
+                                       no_handles: &mut usize,
+                                       buffer: &mut *mut EFIHandle)
+                                -> EFIStatus,
     locate_protocol_fn: unsafe fn(protocol: &EFIGuid, registration: *const c_void, interface: &mut *mut c_void) -> EFIStatus,
     install_multiple_protocol_interfaces_fn: usize,
     uninstall_multiple_protocol_interfaces_fn: usize,
@@ -253,77 +522,438 @@ pub struct BootServices {
 
     copy_mem_fn: usize,
     set_mem_fn: usize,
-    create_event_ex_fn: usize,
+    create_event_ex_fn: unsafe fn(typ: u32,
+                                  notify_tpl: EFITpl,
+                                  notify_function: Option<EFIEventNotify>,
+                                  notify_context: *const c_void,
+                                  event_group: &EFIGuid,
+                                  event: &mut EFIEvent) -> EFIStatus,
 }
 
 impl BootServices {
-    pub fn allocate_pages(&self,
-                          allocate_type: EFIAllocateType,
-                          memory_type: EFIMemoryType,
-                          page_count: u64,
-                          address: &mut u64)
+    /// Allocate pages of physical memory, the memory isn't cleared
+    pub fn allocate_pages(&self, allocate_type: AllocateType,
+                          memory_type: EFIMemoryType, page_count: u64)
+        -> EFIResult<PhysRange>
     {
-        unsafe {
+        let (allocate_type, mut address) = allocate_type.to_raw();
+
+        let status = unsafe {
             (self.allocate_pages_fn)(allocate_type, memory_type,
-                                     page_count, address);
-        }
+                                     page_count, &mut address)
+        };
+
+        status.into_result("BootServices::allocate_pages")?;
+
+        Ok(PhysRange::new(address, page_count))
+    }
+
+    /// Allocate pages that ends at or below `max_address`, i.e for
+    /// memory that needs to be below 4GiB
+    pub fn allocate_below(&self, max_address: u64,
+                          memory_type: EFIMemoryType, page_count: u64)
+        -> EFIResult<PhysRange>
+    {
+        self.allocate_pages(AllocateType::MaxAddress(max_address),
+                            memory_type, page_count)
+    }
+
+    /// Allocate pages at `address`, the error status is `NotFound` if
+    /// the pages are in use
+    pub fn allocate_at(&self, address: u64,
+                       memory_type: EFIMemoryType, page_count: u64)
+        -> EFIResult<PhysRange>
+    {
+        self.allocate_pages(AllocateType::Address(address),
+                            memory_type, page_count)
+    }
+
+    /// Free pages allocated with `allocate_pages`
+    ///
+    /// # Safety
+    /// Nothing can use the memory after it's freed
+    pub unsafe fn free_pages(&self, range: PhysRange) -> EFIResult<()> {
+        let status = (self.free_pages_fn)(range.start, range.page_count);
+
+        status.into_result("BootServices::free_pages")
     }
 
     /// A Function to allocate from a pool selected by the ´memory_type´
-    /// TODO(patrik): Return the pointer insteed
-    pub fn allocate_pool(&self, memory_type: EFIMemoryType,
-                         size: usize, buffer: &mut *mut u8)
+    /// and return the pointer to the allocated memory
+    pub fn allocate_pool(&self, memory_type: EFIMemoryType, size: usize)
+        -> EFIResult<*mut u8>
     {
+        // Pointer for the allocated memory
+        let mut buffer = core::ptr::null_mut();
+
         // Allocate the memory
         let status = unsafe {
-            (self.allocate_pool_fn)(memory_type, size as u64, buffer)
+            (self.allocate_pool_fn)(memory_type, size as u64, &mut buffer)
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Remove the panic
-            panic!("Failed to allocate from pool: {:?}", memory_type);
-        }
+        status.into_result("BootServices::allocate_pool")?;
+
+        Ok(buffer)
     }
 
     /// Free the memory allocated from a pool
-    pub fn free_pool(&self, buffer: *mut u8) {
+    ///
+    /// # Safety
+    /// The buffer needs to be a pointer returned from `allocate_pool`
+    /// and it can't be used after this call
+    pub unsafe fn free_pool(&self, buffer: *mut u8) -> EFIResult<()> {
+        let status = (self.free_pool_fn)(buffer);
+
+        status.into_result("BootServices::free_pool")
+    }
+
+    /// Create a event, the `notify_function` is called by the firmware
+    /// with the `notify_context` when the event is signaled or waited on
+    /// depending on the type of the event
+    ///
+    /// # Safety
+    /// The `notify_context` needs to be valid for as long as the event
+    /// exists because the firmware gives it to the notify function
+    pub unsafe fn create_event(&self,
+                               typ: EFIEventType,
+                               notify_tpl: EFITpl,
+                               notify_function: Option<EFIEventNotify>,
+                               notify_context: *mut c_void)
+        -> EFIResult<EventGuard<'_>>
+    {
+        let mut event = EFIEvent::default();
+
+        let status = (self.create_event_fn)(typ.bits(), notify_tpl,
+                                            notify_function, notify_context,
+                                            &mut event);
+
+        status.into_result("BootServices::create_event")?;
+
+        Ok(EventGuard::new(self, event))
+    }
+
+    /// Create a event in a event group, the event is signaled when
+    /// any event in the group is signaled
+    ///
+    /// # Safety
+    /// The `notify_context` needs to be valid for as long as the event
+    /// exists because the firmware gives it to the notify function
+    pub unsafe fn create_event_ex(&self,
+                                  typ: EFIEventType,
+                                  notify_tpl: EFITpl,
+                                  notify_function: Option<EFIEventNotify>,
+                                  notify_context: *const c_void,
+                                  event_group: &EFIGuid)
+        -> EFIResult<EventGuard<'_>>
+    {
+        let mut event = EFIEvent::default();
+
+        let status = (self.create_event_ex_fn)(typ.bits(), notify_tpl,
+                                               notify_function,
+                                               notify_context,
+                                               event_group, &mut event);
+
+        status.into_result("BootServices::create_event_ex")
+            .map_err(|err| err.with_guid(event_group))?;
+
+        Ok(EventGuard::new(self, event))
+    }
+
+    /// Create a timer event without a notify function, the timer needs
+    /// to be set with `set_timer`
+    pub fn create_timer(&self) -> EFIResult<EventGuard<'_>> {
+        // Safe because the timer don't have a notify function
+        unsafe {
+            self.create_event(EFIEventType::TIMER, EFITpl::Application,
+                              None, core::ptr::null_mut())
+        }
+    }
+
+    /// Set or cancel a timer, the `trigger_time` is in 100ns units
+    /// and a periodic timer is signaled every `trigger_time`
+    pub fn set_timer(&self, event: EFIEvent,
+                     typ: EFITimerDelay, trigger_time: u64)
+        -> EFIResult<()>
+    {
+        let status = unsafe {
+            (self.set_timer_fn)(event, typ, trigger_time)
+        };
+
+        status.into_result("BootServices::set_timer")
+    }
+
+    /// Stop and wait until any of the events are signaled and return the
+    /// index of the event that was signaled
+    pub fn wait_for_any(&self, events: &[EFIEvent]) -> EFIResult<usize> {
+        let mut index = 0;
+
+        let status = unsafe {
+            (self.wait_for_event_fn)(events.len(), events.as_ptr(),
+                                     &mut index)
+        };
+
+        status.into_result("BootServices::wait_for_event")?;
+
+        Ok(index)
+    }
+
+    /// Signal a event
+    pub fn signal_event(&self, event: EFIEvent) -> EFIResult<()> {
+        let status = unsafe {
+            (self.signal_event_fn)(event)
+        };
+
+        status.into_result("BootServices::signal_event")
+    }
+
+    /// Check if a event is signaled without waiting for it
+    pub fn check_event(&self, event: EFIEvent) -> EFIResult<bool> {
         let status = unsafe {
-            (self.free_pool_fn)(buffer)
+            (self.check_event_fn)(event)
         };
 
-        if status != EFIStatus::Success {
-            panic!("Failed to free pool");
+        // NotReady means that the event is not signaled yet
+        if status == EFIStatus::NotReady {
+            return Ok(false);
         }
+
+        status.into_result("BootServices::check_event")?;
+
+        Ok(true)
+    }
+
+    /// Close a event, only used by the `EventGuard` because the events
+    /// owned by the firmware should never be closed
+    pub(crate) fn close_event(&self, event: EFIEvent) -> EFIResult<()> {
+        let status = unsafe {
+            (self.close_event_fn)(event)
+        };
+
+        status.into_result("BootServices::close_event")
     }
 
-    /// Handles a protocol and returns the handle pointer
-    pub fn handle_protocol(&self, handle: EFIHandle, guid: &EFIGuid)
-        -> *mut c_void
+    /// Stall the processor for atleast the number of microseconds
+    pub fn stall(&self, microseconds: usize) -> EFIResult<()> {
+        let status = unsafe {
+            (self.stall_fn)(microseconds)
+        };
+
+        status.into_result("BootServices::stall")
+    }
+
+    /// Set the watchdog timer to reset the system after `timeout`
+    /// seconds, a timeout of 0 disables the watchdog
+    /// NOTE(patrik): The firmware arms the watchdog with a 5 minute
+    /// timeout before starting the image
+    pub fn set_watchdog_timer(&self, timeout: usize, watchdog_code: u64)
+        -> EFIResult<()>
+    {
+        let status = unsafe {
+            (self.set_watchdog_timer_fn)(timeout, watchdog_code,
+                                         0, core::ptr::null())
+        };
+
+        status.into_result("BootServices::set_watchdog_timer")
+    }
+
+    /// Get the next value of the platforms monotonic counter
+    pub fn get_next_monotonic_count(&self) -> EFIResult<u64> {
+        let mut count = 0;
+
+        let status = unsafe {
+            (self.get_next_monotonic_count_fn)(&mut count)
+        };
+
+        status.into_result("BootServices::get_next_monotonic_count")?;
+
+        Ok(count)
+    }
+
+    /// Handles a protocol and returns a reference to the protocol
+    pub fn handle_protocol<P: Protocol>(&self, handle: EFIHandle)
+        -> EFIResult<&P>
     {
         // Pointer for the handle
         let mut ptr = core::ptr::null_mut();
 
         // Get the handle to the protocol
         let status = unsafe {
-            (self.handle_protocol_fn)(handle, guid, &mut ptr)
+            (self.handle_protocol_fn)(handle, &P::GUID, &mut ptr)
+        };
+
+        // Check the status
+        status.into_result("BootServices::handle_protocol")
+            .map_err(|err| err.with_guid(&P::GUID))?;
+
+        // Return the protocol
+        Ok(unsafe { &*(ptr as *const P) })
+    }
+
+    /// Open a protocol on a handle, the `agent_handle` is the image
+    /// opening the protocol and the `controller_handle` is only used
+    /// by drivers and should be 0 otherwise, the protocol is closed
+    /// when the returned guard is dropped
+    pub fn open_protocol<P: Protocol>(&self,
+                                      handle: EFIHandle,
+                                      agent_handle: EFIHandle,
+                                      controller_handle: EFIHandle,
+                                      attributes: OpenProtocolAttributes)
+        -> EFIResult<ProtocolGuard<'_, P>>
+    {
+        // Pointer for the protocol
+        let mut ptr = core::ptr::null_mut();
+
+        // Open the protocol
+        let status = unsafe {
+            (self.open_protocol_fn)(handle, &P::GUID, &mut ptr,
+                                    agent_handle, controller_handle,
+                                    attributes.bits())
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Print the guid
-            // TODO(patrik): Remove the panic
-            panic!("Failed to handle protocol");
+        status.into_result("BootServices::open_protocol")
+            .map_err(|err| err.with_guid(&P::GUID))?;
+
+        // When the protocol is opened with TEST_PROTOCOL we don't get
+        // a interface back so we can't create a guard for it
+        if ptr.is_null() {
+            return Err(EFIError::new(EFIStatus::Unsupported,
+                                     "BootServices::open_protocol")
+                       .with_guid(&P::GUID));
         }
 
-        // Return the handle
-        ptr
+        let interface = unsafe { &*(ptr as *const P) };
+
+        Ok(ProtocolGuard::new(self, interface, handle,
+                              agent_handle, controller_handle))
+    }
+
+    /// Close a protocol opened by `open_protocol`, most of the time this
+    /// is done by dropping the `ProtocolGuard`
+    pub fn close_protocol(&self,
+                          handle: EFIHandle,
+                          protocol: &EFIGuid,
+                          agent_handle: EFIHandle,
+                          controller_handle: EFIHandle)
+        -> EFIResult<()>
+    {
+        let status = unsafe {
+            (self.close_protocol_fn)(handle, protocol,
+                                     agent_handle, controller_handle)
+        };
+
+        status.into_result("BootServices::close_protocol")
+            .map_err(|err| err.with_guid(protocol))
+    }
+
+    /// Load a image in to memory and return the handle of the image,
+    /// the image is started with `start_image`
+    pub fn load_image(&self, parent_image_handle: EFIHandle,
+                      source: LoadImageSource)
+        -> EFIResult<EFIHandle>
+    {
+        let (device_path, buffer) = match source {
+            LoadImageSource::FromPath(path) => (path as *const _, &[][..]),
+            LoadImageSource::FromBuffer { buffer, file_path } => {
+                let path = file_path
+                    .map_or(core::ptr::null(), |path| path as *const _);
+
+                (path, buffer)
+            }
+        };
+
+        // A empty buffer means that the firmware should load the image
+        // from the device path
+        let source_buffer = if buffer.is_empty() {
+            core::ptr::null()
+        } else {
+            buffer.as_ptr()
+        };
+
+        let mut image_handle = 0;
+        let status = unsafe {
+            (self.load_image_fn)(false, parent_image_handle, device_path,
+                                 source_buffer, buffer.len(),
+                                 &mut image_handle)
+        };
+
+        // NOTE(patrik): The firmware loads the image even if it fails the
+        // security check, the image can't be started so we unload it
+        if status == EFIStatus::SecurityViolation && image_handle != 0 {
+            let _ = self.unload_image(image_handle);
+        }
+
+        status.into_result("BootServices::load_image")?;
+
+        Ok(image_handle)
+    }
+
+    /// Start a image loaded by `load_image` and wait for it to exit,
+    /// returns the status the image exited with. The image can exit with
+    /// any value so the status is kept as the raw value
+    pub fn start_image(&self, image_handle: EFIHandle) -> ImageExit {
+        let mut exit_data_size = 0;
+        let mut exit_data = core::ptr::null_mut();
+
+        let status = unsafe {
+            (self.start_image_fn)(image_handle, &mut exit_data_size,
+                                  &mut exit_data)
+        };
+
+        // The exit data is a null-terminated string and maybe some data
+        // after it, the image allocated the data so we need to free it
+        let exit_data = if exit_data.is_null() {
+            None
+        } else {
+            unsafe {
+                let data = core::slice::from_raw_parts(
+                    exit_data, exit_data_size / core::mem::size_of::<u16>());
+                let string = CStr16::from_u16_until_nul(data)
+                    .map(String::from)
+                    .ok();
+
+                let _ = self.free_pool(exit_data as *mut u8);
+
+                string
+            }
+        };
+
+        ImageExit {
+            raw_status: status,
+            exit_data,
+        }
+    }
+
+    /// Unload a image that was loaded but not started, or a started
+    /// image that supports unloading
+    pub fn unload_image(&self, image_handle: EFIHandle) -> EFIResult<()> {
+        let status = unsafe { (self.unload_image_fn)(image_handle) };
+
+        status.into_result("BootServices::unload_image")
+    }
+
+    /// Exit the image with a status, this returns to the image that
+    /// started the image so the function only returns if it fails
+    ///
+    /// # Safety
+    /// The image is unloaded by the firmware so nothing can be in use
+    /// that the image owns i.e events with notify functions in the image
+    pub unsafe fn exit(&self, image_handle: EFIHandle,
+                       exit_status: EFIStatus) -> EFIResult<()>
+    {
+        let status = (self.exit_fn)(image_handle, exit_status,
+                                    0, core::ptr::null());
+
+        status.into_result("BootServices::exit")
     }
 
-    /// Exit boot services
+    /// Exit boot services, if the map key is out of date the error
+    /// status is `InvalidParameter` and the memory map needs to be
+    /// retrived again
     pub fn exit_boot_services(&self,
                               image_handle: EFIHandle,
-                              map_key: u64) -> Option<()>
+                              map_key: u64) -> EFIResult<()>
     {
         // Call the function
         let status = unsafe {
@@ -331,37 +961,106 @@ impl BootServices {
         };
 
         // Check the status
-        if status == EFIStatus::InvalidParameter {
-            None
-        } else if status == EFIStatus::Success {
-            Some(())
-        } else {
-            panic!("Exit boot services failed: {:?}", status);
-        }
+        status.into_result("BootServices::exit_boot_services")
     }
 
-    /// Locate a protocol
-    pub fn locate_protocol(&self, protocol: &EFIGuid) -> *mut c_void {
+    /// Locate the first instance of a protocol
+    pub fn locate_protocol<P: Protocol>(&self) -> EFIResult<&P> {
         // Pointer to the protocol
         let mut ptr = core::ptr::null_mut();
 
         // Get the handle to protocol
         let status = unsafe {
-            (self.locate_protocol_fn)(protocol, core::ptr::null_mut(), &mut ptr)
+            (self.locate_protocol_fn)(&P::GUID, core::ptr::null_mut(),
+                                      &mut ptr)
         };
 
         // Check the status
-        if status != EFIStatus::Success {
-            // TODO(patrik): Print the guid
-            // TODO(patrik): Remove the panic
-            panic!("Failed to locate protocol");
+        status.into_result("BootServices::locate_protocol")
+            .map_err(|err| err.with_guid(&P::GUID))?;
+
+        // Return the protocol
+        Ok(unsafe { &*(ptr as *const P) })
+    }
+
+    /// Locate the handles matching the search and put them in the
+    /// buffer, returns the number of handles written to the buffer
+    /// NOTE(patrik): `ByRegisterNotify` is not supported because we don't
+    /// wrap `register_protocol_notify` yet
+    pub fn locate_handle(&self,
+                         search_type: EFILocateSearchType,
+                         protocol: Option<&EFIGuid>,
+                         buffer: &mut [EFIHandle])
+        -> EFIResult<usize>
+    {
+        // Get the protocol pointer, null is used for `AllHandles`
+        let protocol_ptr = protocol
+            .map_or(core::ptr::null(), |guid| guid as *const EFIGuid);
+
+        // The size of the buffer is in bytes
+        let mut buffer_size = core::mem::size_of_val(buffer);
+
+        let status = unsafe {
+            (self.locate_handle_fn)(search_type, protocol_ptr,
+                                    core::ptr::null(), &mut buffer_size,
+                                    buffer.as_mut_ptr())
+        };
+
+        status.into_result("BootServices::locate_handle")
+            .map_err(|err| match protocol {
+                Some(guid) => err.with_guid(guid),
+                None => err,
+            })?;
+
+        Ok(buffer_size / core::mem::size_of::<EFIHandle>())
+    }
+
+    /// Locate the handles matching the search, the firmware allocates the
+    /// buffer for us and we copy the handles in to a vector
+    pub fn locate_handle_buffer(&self,
+                                search_type: EFILocateSearchType,
+                                protocol: Option<&EFIGuid>)
+        -> EFIResult<Vec<EFIHandle>>
+    {
+        // Get the protocol pointer, null is used for `AllHandles`
+        let protocol_ptr = protocol
+            .map_or(core::ptr::null(), |guid| guid as *const EFIGuid);
+
+        let mut count = 0;
+        let mut buffer = core::ptr::null_mut();
+
+        let status = unsafe {
+            (self.locate_handle_buffer_fn)(search_type, protocol_ptr,
+                                           core::ptr::null(), &mut count,
+                                           &mut buffer)
+        };
+
+        status.into_result("BootServices::locate_handle_buffer")
+            .map_err(|err| match protocol {
+                Some(guid) => err.with_guid(guid),
+                None => err,
+            })?;
+
+        // Copy the handles so we can give the buffer back to the firmware
+        let handles = unsafe {
+            core::slice::from_raw_parts(buffer, count).to_vec()
+        };
+
+        unsafe {
+            self.free_pool(buffer as *mut u8)?;
         }
 
-        // Return the handle
-        ptr
+        Ok(handles)
+    }
+
+    /// Find all the handles that supports the protocol
+    pub fn find_handles<P: Protocol>(&self) -> EFIResult<Vec<EFIHandle>> {
+        self.locate_handle_buffer(EFILocateSearchType::ByProtocol,
+                                  Some(&P::GUID))
     }
 
-    pub fn get_memory_map_size(&self) -> usize {
+    /// Get the size in bytes needed for a buffer to hold the memory map
+    pub fn get_memory_map_size(&self) -> EFIResult<usize> {
         // Create some variables that the memory map call gives us
         let mut map_size = 0;
         let mut map_key = 0;
@@ -369,7 +1068,7 @@ impl BootServices {
         let mut entry_version = 0;
 
         // Get some infomation about the memory
-        let _status = unsafe {
+        let status = unsafe {
             (self.get_memory_map_fn)(
                 &mut map_size,
                 core::ptr::null_mut(),
@@ -379,17 +1078,24 @@ impl BootServices {
             )
         };
 
-        // TODO(patrik): Check the status
-
-        // Allocate a buffer to hold the memory map
-        // let mut buffer = vec![0u8; map_size as usize];
+        // We expect BufferTooSmall here because we didn't give the
+        // function a buffer
+        if status != EFIStatus::BufferTooSmall {
+            status.into_result("BootServices::get_memory_map_size")?;
+        }
 
-        map_size as usize
+        Ok(map_size as usize)
     }
 
     /// Get a memory map
-    pub fn get_memory_map<'a>(&self, buffer: *mut u8, buffer_size: usize) -> EFIMemoryMap<'a> {
-
+    ///
+    /// # Safety
+    /// The buffer needs to be valid for writes of `buffer_size` bytes
+    /// and outlive the returned memory map
+    pub unsafe fn get_memory_map<'a>(&self, buffer: *mut u8,
+                                     buffer_size: usize)
+        -> EFIResult<EFIMemoryMap<'a>>
+    {
         // Get a pointer to the buffer
         let ptr = buffer as *mut MemoryDescriptor;
 
@@ -399,22 +1105,41 @@ impl BootServices {
         let mut entry_version = 0;
 
         // Get the memory map and put it in the buffer
-        let status = unsafe {
-            (self.get_memory_map_fn)(
-                &mut map_size,
-                ptr,
-                &mut map_key,
-                &mut entry_size,
-                &mut entry_version,
-            )
-        };
+        let status = (self.get_memory_map_fn)(
+            &mut map_size,
+            ptr,
+            &mut map_key,
+            &mut entry_size,
+            &mut entry_version,
+        );
 
-        if status != EFIStatus::Success {
-            panic!("Failed to retrive the memory map: {:?}", status);
-        }
+        status.into_result("BootServices::get_memory_map")?;
 
         // Return a instance of a memory map struct
-        EFIMemoryMap::new(unsafe { core::slice::from_raw_parts(buffer, buffer_size) }, map_size, entry_size, map_key)
+        let buffer = core::slice::from_raw_parts(buffer, buffer_size);
+        Ok(EFIMemoryMap::new(buffer, map_size, entry_size, map_key))
+    }
+
+    /// Get a owned copy of the memory map
+    pub fn memory_map(&self) -> EFIResult<MemoryMap> {
+        loop {
+            // Allocating the buffer can change the map so make room for
+            // some more descriptors
+            let size = self.get_memory_map_size()? +
+                8 * core::mem::size_of::<MemoryDescriptor>();
+            let mut buffer = alloc::vec![0u8; size];
+
+            let memory_map = unsafe {
+                self.get_memory_map(buffer.as_mut_ptr(), size)
+            };
+
+            match memory_map {
+                Ok(memory_map) =>
+                    return Ok(MemoryMap::from_efi_map(&memory_map)),
+                Err(err) if err.status() == EFIStatus::BufferTooSmall => {}
+                Err(err) => return Err(err),
+            }
+        }
     }
 }
 
@@ -428,8 +1153,8 @@ pub struct SystemTable<'a> {
     firmware_vendor: usize,
     firmware_revision: u32,
 
-    console_in_handle: usize,
-    con_in: usize,
+    console_in_handle: EFIHandle,
+    pub console_in: &'a SimpleTextInputInterface,
 
     console_out_handle: usize,
     pub console_out: &'a SimpleTextOutputInterface,
@@ -437,9 +1162,55 @@ pub struct SystemTable<'a> {
     standard_error_handle: usize,
     stderr: &'a SimpleTextOutputInterface,
 
-    runtime_services: usize,
+    pub runtime_services: &'a RuntimeServices,
     pub boot_services: &'a BootServices,
 
-    number_of_table_entries: u64,
-    configuration_table: usize,
+    number_of_table_entries: usize,
+    configuration_table: *const EFIConfigurationTable,
+}
+
+impl<'a> SystemTable<'a> {
+    /// Get the extended input protocol for the console, it's not
+    /// supported by all the firmware so it can fail
+    pub fn console_in_ex(&self)
+        -> EFIResult<&'a EFISimpleTextInputExProtocol>
+    {
+        self.boot_services
+            .handle_protocol::<EFISimpleTextInputExProtocol>(
+                self.console_in_handle)
+    }
+
+    /// Get all the entries in the configuration table
+    pub fn configuration_tables(&self) -> &'a [EFIConfigurationTable] {
+        if self.configuration_table.is_null() {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(self.configuration_table,
+                                        self.number_of_table_entries)
+        }
+    }
+
+    /// Find the address of the table with the guid
+    pub fn find_configuration_table(&self, guid: &EFIGuid) -> Option<usize> {
+        self.configuration_tables()
+            .iter()
+            .find(|entry| entry.vendor_guid == *guid)
+            .map(|entry| entry.vendor_table)
+    }
+
+    /// Find the address of the ACPI RSDP, the ACPI 2.0 RSDP is
+    /// preferred over the ACPI 1.0 RSDP
+    pub fn acpi_rsdp(&self) -> Option<usize> {
+        self.find_configuration_table(&ACPI_20_TABLE_GUID)
+            .or_else(|| self.find_configuration_table(&ACPI_TABLE_GUID))
+    }
+
+    /// Find the address of the SMBIOS entry point, the 64-bit SMBIOS 3
+    /// entry point is preferred over the 32-bit entry point
+    pub fn smbios_entry_point(&self) -> Option<usize> {
+        self.find_configuration_table(&SMBIOS3_TABLE_GUID)
+            .or_else(|| self.find_configuration_table(&SMBIOS_TABLE_GUID))
+    }
 }
@@ -1,5 +1,8 @@
 use crate::{ VirtualAddress, PhysicalAddress };
+use crate::{ EFIStatus, EFIError, EFIResult };
+
 use alloc::vec::Vec;
+use core::ops::Deref;
 
 // Flags for the memory attributes
 // TODO(patrik): Change the names
@@ -24,8 +27,11 @@ bitflags! {
 }
 
 // Memory Types
+// NOTE(patrik): The types from 0x70000000 to 0x7fffffff are for the OEM
+// and the types from 0x80000000 are for the OS loader, we use them to mark
+// the memory the bootloader gives to the kernel
 #[derive(PartialEq, Clone, Copy, Debug)]
-#[repr(C)]
+#[repr(u32)]
 #[allow(dead_code)]
 pub enum EFIMemoryType {
     ReservedMemoryType      = 0x00000000,
@@ -43,6 +49,88 @@ pub enum EFIMemoryType {
     MemoryMappedIOPortSpace = 0x0000000c,
     PalCode                 = 0x0000000d,
     PersistentMemory        = 0x0000000e,
+
+    /// The data the bootloader gives to the kernel i.e the boot info
+    OsLoaderBootInfo        = 0x80000000,
+    /// The segments of the kernel
+    OsLoaderKernel          = 0x80000001,
+}
+
+impl EFIMemoryType {
+    /// All the memory types we know about
+    pub const ALL: [EFIMemoryType; 17] = [
+        EFIMemoryType::ReservedMemoryType,
+        EFIMemoryType::LoaderCode,
+        EFIMemoryType::LoaderData,
+        EFIMemoryType::BootServicesCode,
+        EFIMemoryType::BootServicesData,
+        EFIMemoryType::RuntimeServicesCode,
+        EFIMemoryType::RuntimeServicesData,
+        EFIMemoryType::ConventionalMemory,
+        EFIMemoryType::UnusableMemory,
+        EFIMemoryType::ACPIReclaimMemory,
+        EFIMemoryType::ACPIMemoryNVS,
+        EFIMemoryType::MemoryMappedIO,
+        EFIMemoryType::MemoryMappedIOPortSpace,
+        EFIMemoryType::PalCode,
+        EFIMemoryType::PersistentMemory,
+        EFIMemoryType::OsLoaderBootInfo,
+        EFIMemoryType::OsLoaderKernel,
+    ];
+
+    /// Convert a type from the firmware memory map, None if it isn't a
+    /// type we know about i.e the OEM types or a newer type
+    pub fn from_raw(memory_type: u32) -> Option<EFIMemoryType> {
+        Self::ALL.iter().copied().find(|&known| known as u32 == memory_type)
+    }
+
+    /// What the memory can be used for
+    pub fn class(&self) -> MemoryClass {
+        match self {
+            EFIMemoryType::ConventionalMemory => MemoryClass::Usable,
+
+            EFIMemoryType::BootServicesCode |
+            EFIMemoryType::BootServicesData => MemoryClass::Reclaimable,
+
+            EFIMemoryType::LoaderCode |
+            EFIMemoryType::LoaderData |
+            EFIMemoryType::OsLoaderBootInfo |
+            EFIMemoryType::OsLoaderKernel => MemoryClass::Loader,
+
+            EFIMemoryType::ACPIReclaimMemory => MemoryClass::AcpiReclaimable,
+            EFIMemoryType::ACPIMemoryNVS => MemoryClass::AcpiNvs,
+
+            EFIMemoryType::MemoryMappedIO |
+            EFIMemoryType::MemoryMappedIOPortSpace => MemoryClass::Mmio,
+
+            EFIMemoryType::ReservedMemoryType |
+            EFIMemoryType::RuntimeServicesCode |
+            EFIMemoryType::RuntimeServicesData |
+            EFIMemoryType::UnusableMemory |
+            EFIMemoryType::PalCode |
+            EFIMemoryType::PersistentMemory => MemoryClass::Reserved,
+        }
+    }
+}
+
+/// What a region of memory can be used for, the classes groups the
+/// memory types the OS handles the same way
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum MemoryClass {
+    /// Free memory
+    Usable,
+    /// Memory the boot services uses, free after `exit_boot_services`
+    Reclaimable,
+    /// Memory the loader allocated i.e the kernel and the boot info
+    Loader,
+    /// The ACPI tables, free after the OS have read the tables
+    AcpiReclaimable,
+    /// Memory the firmware needs for ACPI, can't be used by the OS
+    AcpiNvs,
+    /// Memory mapped devices
+    Mmio,
+    /// Memory the OS can't use i.e the runtime services
+    Reserved,
 }
 
 #[derive(PartialEq, Clone, Copy, Debug)]
@@ -54,12 +142,91 @@ pub enum EFIAllocateType {
     AllocateAddress    = 0x03,
 }
 
+/// The size of the pages the firmware allocates
+pub const PAGE_SIZE: u64 = 4096;
+
+/// Where `BootServices::allocate_pages` should allocate the pages
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AllocateType {
+    /// Any pages the firmware wants to give us
+    AnyPages,
+    /// Pages that ends at or below the address
+    MaxAddress(u64),
+    /// Pages that starts at the address, the address needs to be
+    /// page aligned
+    Address(u64),
+}
+
+impl AllocateType {
+    /// The type and the address the firmware wants
+    pub(crate) fn to_raw(self) -> (EFIAllocateType, u64) {
+        match self {
+            AllocateType::AnyPages =>
+                (EFIAllocateType::AllocateAnyPages, 0),
+            AllocateType::MaxAddress(address) =>
+                (EFIAllocateType::AllocateMaxAddress, address),
+            AllocateType::Address(address) =>
+                (EFIAllocateType::AllocateAddress, address),
+        }
+    }
+}
+
+/// A range of physical pages
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct PhysRange {
+    /// The address of the first page
+    pub start: u64,
+    /// The number of pages in the range
+    pub page_count: u64,
+}
+
+impl PhysRange {
+    pub fn new(start: u64, page_count: u64) -> Self {
+        Self {
+            start,
+            page_count,
+        }
+    }
+
+    /// The size of the range in bytes
+    pub fn size(&self) -> u64 {
+        self.page_count * PAGE_SIZE
+    }
+
+    /// The address after the last byte in the range
+    pub fn end(&self) -> u64 {
+        self.start + self.size()
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.start && address < self.end()
+    }
+
+    /// The pointer to the start of the range, the memory is identity
+    /// mapped while we have the boot services
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.start as *mut u8
+    }
+}
+
+/// Align a address or a size up to the next page
+pub const fn page_align_up(value: u64) -> u64 {
+    (value + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+/// The number of pages needed for a size in bytes
+pub const fn pages_for(size: u64) -> u64 {
+    page_align_up(size) / PAGE_SIZE
+}
+
 // Memory Descritptor represents a chunk of memory with some infomation
 // like the type, start address and more
+// NOTE(patrik): The firmware can use types we don't know about so the
+// type is kept as the raw value
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct MemoryDescriptor {
-    pub memory_type: EFIMemoryType,
+    memory_type: u32,
     pad: u32,
     pub physical_start: PhysicalAddress,
     pub virtual_start: VirtualAddress,
@@ -67,6 +234,30 @@ pub struct MemoryDescriptor {
     pub attribute: EFIMemoryAttribute,
 }
 
+impl MemoryDescriptor {
+    /// The type of the memory, None if it isn't a type we know about
+    pub fn memory_type(&self) -> Option<EFIMemoryType> {
+        EFIMemoryType::from_raw(self.memory_type)
+    }
+
+    /// The type of the memory as the firmware reported it
+    pub fn raw_memory_type(&self) -> u32 {
+        self.memory_type
+    }
+
+    /// What the memory can be used for, the types we don't know about
+    /// are reserved
+    pub fn class(&self) -> MemoryClass {
+        self.memory_type()
+            .map_or(MemoryClass::Reserved, |memory_type| memory_type.class())
+    }
+
+    /// The physical range of the memory
+    pub fn range(&self) -> PhysRange {
+        PhysRange::new(self.physical_start.0, self.number_of_pages)
+    }
+}
+
 // A Iterator for the memory map
 pub struct EFIMemoryMapIterator<'a> {
     buffer: &'a [u8],
@@ -86,7 +277,7 @@ impl<'a> Iterator for EFIMemoryMapIterator<'a> {
             unsafe {
                 // Calculate the offset inside the map we are
                 // and get the pointer for that entry
-                let ptr = self.buffer.as_ptr().offset((self.index * self.entry_size) as isize);
+                let ptr = self.buffer.as_ptr().add(self.index * self.entry_size);
 
                 // Cast the pointer to a memory descriptor
                 let ptr = ptr as *const MemoryDescriptor;
@@ -120,7 +311,7 @@ impl<'a> EFIMemoryMap<'a> {
         -> Self
     {
         Self {
-            buffer: &buffer[..],
+            buffer,
             map_size,
             entry_size,
             map_key
@@ -129,13 +320,13 @@ impl<'a> EFIMemoryMap<'a> {
 
     /// Return a new iterator for the memory map
     /// NOTE(patrik): Can be called multiple times
-    pub fn entries(&self) -> EFIMemoryMapIterator {
+    pub fn entries(&self) -> EFIMemoryMapIterator<'_> {
         let num_entries = (self.map_size / self.entry_size) as usize;
         let entry_size = self.entry_size as usize;
 
         EFIMemoryMapIterator {
-            buffer: &self.buffer,
-            entry_size: entry_size,
+            buffer: self.buffer,
+            entry_size,
             num_entries,
             index: 0
         }
@@ -145,3 +336,277 @@ impl<'a> EFIMemoryMap<'a> {
         self.map_key
     }
 }
+
+/// A owned copy of the memory map the firmware gave us, the map can be
+/// sorted and the regions next to each other merged
+#[derive(Clone, Debug)]
+pub struct MemoryMap {
+    descriptors: Vec<MemoryDescriptor>,
+    key: u64,
+}
+
+impl MemoryMap {
+    /// Create a empty map with room for `capacity` descriptors, the map
+    /// can be filled with `fill` without allocating
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            descriptors: Vec::with_capacity(capacity),
+            key: 0,
+        }
+    }
+
+    /// Copy the memory map from the firmware
+    pub fn from_efi_map(memory_map: &EFIMemoryMap) -> Self {
+        Self {
+            descriptors: memory_map.entries().collect(),
+            key: memory_map.key(),
+        }
+    }
+
+    /// Replace the descriptors with the memory map from the firmware
+    /// without allocating, useful after `exit_boot_services` when we
+    /// can't allocate anymore. The error status is `BufferTooSmall` if
+    /// the map doesn't fit
+    pub fn fill(&mut self, memory_map: &EFIMemoryMap) -> EFIResult<()> {
+        let count = memory_map.entries().count();
+        if count > self.descriptors.capacity() {
+            return Err(EFIError::new(EFIStatus::BufferTooSmall,
+                                     "MemoryMap::fill"));
+        }
+
+        self.descriptors.clear();
+        self.descriptors.extend(memory_map.entries());
+        self.key = memory_map.key();
+
+        Ok(())
+    }
+
+    /// The key of the map the descriptors was copied from
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Sort the descriptors by the physical address
+    pub fn sort(&mut self) {
+        // NOTE(patrik): The unstable sort doesn't allocate
+        self.descriptors.sort_unstable_by_key(|d| d.physical_start.0);
+    }
+
+    /// Sort the descriptors and merge the regions that are next to each
+    /// other and have the same type and attributes
+    /// NOTE(patrik): This doesn't allocate so it can be used after
+    /// `exit_boot_services`
+    pub fn coalesce(&mut self) {
+        self.sort();
+
+        self.descriptors.dedup_by(|next, prev| {
+            let mergeable = next.memory_type == prev.memory_type &&
+                next.attribute == prev.attribute &&
+                prev.range().end() == next.physical_start.0;
+
+            if mergeable {
+                prev.number_of_pages += next.number_of_pages;
+            }
+
+            mergeable
+        });
+    }
+
+    /// Iterate over the regions with a class
+    pub fn regions(&self, class: MemoryClass)
+        -> impl Iterator<Item = &MemoryDescriptor> + '_
+    {
+        self.descriptors.iter().filter(move |d| d.class() == class)
+    }
+
+    /// The number of pages with a class
+    pub fn total_pages(&self, class: MemoryClass) -> u64 {
+        self.regions(class).map(|d| d.number_of_pages).sum()
+    }
+
+    /// The number of bytes with a class
+    pub fn total_size(&self, class: MemoryClass) -> u64 {
+        self.total_pages(class) * PAGE_SIZE
+    }
+
+    /// The address after the last byte of usable memory
+    pub fn highest_usable_address(&self) -> Option<u64> {
+        self.regions(MemoryClass::Usable)
+            .map(|d| d.range().end())
+            .max()
+    }
+
+    /// Find `page_count` usable pages that ends at or below `below`,
+    /// the lowest range is returned. The first page is never returned
+    /// so a range can't start at the null pointer
+    pub fn find_free_range(&self, page_count: u64, below: u64)
+        -> Option<PhysRange>
+    {
+        let size = page_count * PAGE_SIZE;
+
+        self.regions(MemoryClass::Usable)
+            .filter_map(|d| {
+                let range = d.range();
+                let start = range.start.max(PAGE_SIZE);
+                let end = range.end().min(below);
+
+                if start < end && end - start >= size {
+                    Some(PhysRange::new(start, page_count))
+                } else {
+                    None
+                }
+            })
+            .min_by_key(|range| range.start)
+    }
+}
+
+impl Deref for MemoryMap {
+    type Target = [MemoryDescriptor];
+
+    fn deref(&self) -> &[MemoryDescriptor] {
+        &self.descriptors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(memory_type: u32, start: u64, page_count: u64)
+        -> MemoryDescriptor
+    {
+        MemoryDescriptor {
+            memory_type,
+            pad: 0,
+            physical_start: PhysicalAddress(start),
+            virtual_start: VirtualAddress(0),
+            number_of_pages: page_count,
+            attribute: EFIMemoryAttribute::WB,
+        }
+    }
+
+    fn memory_map(descriptors: &[MemoryDescriptor]) -> MemoryMap {
+        MemoryMap {
+            descriptors: descriptors.to_vec(),
+            key: 0,
+        }
+    }
+
+    const CONVENTIONAL: u32 = EFIMemoryType::ConventionalMemory as u32;
+    const BOOT_DATA: u32 = EFIMemoryType::BootServicesData as u32;
+    const RUNTIME_DATA: u32 = EFIMemoryType::RuntimeServicesData as u32;
+
+    #[test]
+    fn coalesce_merges_adjacent_regions_with_the_same_type() {
+        let mut map = memory_map(&[
+            descriptor(CONVENTIONAL, 0x3000, 2),
+            descriptor(CONVENTIONAL, 0x1000, 2),
+            descriptor(BOOT_DATA, 0x5000, 1),
+            descriptor(CONVENTIONAL, 0x6000, 1),
+        ]);
+
+        map.coalesce();
+
+        let ranges: Vec<_> = map.iter()
+            .map(|d| (d.raw_memory_type(), d.physical_start.0,
+                      d.number_of_pages))
+            .collect();
+        assert_eq!(ranges, [
+            (CONVENTIONAL, 0x1000, 4),
+            (BOOT_DATA, 0x5000, 1),
+            (CONVENTIONAL, 0x6000, 1),
+        ]);
+    }
+
+    #[test]
+    fn coalesce_keeps_gaps_and_different_attributes() {
+        let mut uncached = descriptor(CONVENTIONAL, 0x2000, 1);
+        uncached.attribute = EFIMemoryAttribute::UC;
+
+        let mut map = memory_map(&[
+            descriptor(CONVENTIONAL, 0x1000, 1),
+            uncached,
+            descriptor(CONVENTIONAL, 0x4000, 1),
+        ]);
+
+        map.coalesce();
+
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn unknown_types_are_reserved() {
+        let map = memory_map(&[
+            descriptor(0x70000000, 0x1000, 1),
+            descriptor(CONVENTIONAL, 0x2000, 1),
+        ]);
+
+        assert_eq!(map[0].memory_type(), None);
+        assert_eq!(map[0].class(), MemoryClass::Reserved);
+        assert_eq!(map[1].class(), MemoryClass::Usable);
+    }
+
+    #[test]
+    fn total_size_counts_the_class() {
+        let map = memory_map(&[
+            descriptor(CONVENTIONAL, 0x1000, 2),
+            descriptor(BOOT_DATA, 0x3000, 3),
+            descriptor(CONVENTIONAL, 0x6000, 4),
+            descriptor(RUNTIME_DATA, 0xa000, 5),
+        ]);
+
+        assert_eq!(map.total_size(MemoryClass::Usable), 6 * PAGE_SIZE);
+        assert_eq!(map.total_size(MemoryClass::Reclaimable), 3 * PAGE_SIZE);
+        assert_eq!(map.total_size(MemoryClass::Reserved), 5 * PAGE_SIZE);
+        assert_eq!(map.total_size(MemoryClass::Mmio), 0);
+    }
+
+    #[test]
+    fn highest_usable_address_ignores_other_classes() {
+        let map = memory_map(&[
+            descriptor(CONVENTIONAL, 0x1000, 2),
+            descriptor(CONVENTIONAL, 0x8000, 1),
+            descriptor(RUNTIME_DATA, 0x10000, 5),
+        ]);
+
+        assert_eq!(map.highest_usable_address(), Some(0x9000));
+        assert_eq!(memory_map(&[]).highest_usable_address(), None);
+    }
+
+    #[test]
+    fn find_free_range_picks_the_lowest_fit() {
+        let map = memory_map(&[
+            descriptor(CONVENTIONAL, 0x10000, 1),
+            descriptor(CONVENTIONAL, 0x20000, 4),
+            descriptor(BOOT_DATA, 0x30000, 8),
+            descriptor(CONVENTIONAL, 0x40000, 8),
+        ]);
+
+        let range = map.find_free_range(2, u64::MAX).unwrap();
+        assert_eq!((range.start, range.page_count), (0x20000, 2));
+
+        let range = map.find_free_range(8, u64::MAX).unwrap();
+        assert_eq!(range.start, 0x40000);
+
+        assert!(map.find_free_range(9, u64::MAX).is_none());
+    }
+
+    #[test]
+    fn find_free_range_respects_the_limit_and_the_null_page() {
+        let map = memory_map(&[
+            descriptor(CONVENTIONAL, 0, 4),
+            descriptor(CONVENTIONAL, 0x20000, 4),
+        ]);
+
+        // The first page is skipped so only three pages fit at zero
+        let range = map.find_free_range(3, u64::MAX).unwrap();
+        assert_eq!(range.start, PAGE_SIZE);
+        let range = map.find_free_range(4, u64::MAX).unwrap();
+        assert_eq!(range.start, 0x20000);
+
+        // The range has to end at or below the limit
+        assert!(map.find_free_range(4, 0x23000).is_none());
+        let range = map.find_free_range(3, 0x23000).unwrap();
+        assert_eq!(range.start, PAGE_SIZE);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{ EFIGuid, EFIHandle, BootServices };
+
+use core::ops::Deref;
+
+/// A protocol is a struct of function pointers and data the firmware
+/// gives us a pointer to, every protocol is identified by a guid
+///
+/// # Safety
+/// The struct implementing this trait needs to have the same layout as
+/// the protocol the guid represents, otherwise the typed wrappers in
+/// `BootServices` will cast the firmware pointer to the wrong type
+pub unsafe trait Protocol {
+    /// The guid the firmware uses to identify the protocol
+    const GUID: EFIGuid;
+}
+
+// Flags for how a protocol should be opened by `open_protocol`
+bitflags! {
+    pub struct OpenProtocolAttributes: u32 {
+        const BY_HANDLE_PROTOCOL  = 0x00000001;
+        const GET_PROTOCOL        = 0x00000002;
+        const TEST_PROTOCOL       = 0x00000004;
+        const BY_CHILD_CONTROLLER = 0x00000008;
+        const BY_DRIVER           = 0x00000010;
+        const EXCLUSIVE           = 0x00000020;
+    }
+}
+
+/// A protocol opened with `BootServices::open_protocol`, the protocol
+/// is closed when the guard is dropped
+pub struct ProtocolGuard<'a, P: Protocol> {
+    boot_services: &'a BootServices,
+    interface: &'a P,
+
+    handle: EFIHandle,
+    agent_handle: EFIHandle,
+    controller_handle: EFIHandle,
+}
+
+impl<'a, P: Protocol> ProtocolGuard<'a, P> {
+    /// Create a new guard for a opened protocol
+    pub(crate) fn new(boot_services: &'a BootServices,
+                      interface: &'a P,
+                      handle: EFIHandle,
+                      agent_handle: EFIHandle,
+                      controller_handle: EFIHandle)
+        -> Self
+    {
+        Self {
+            boot_services,
+            interface,
+
+            handle,
+            agent_handle,
+            controller_handle,
+        }
+    }
+
+    /// The handle the protocol was opened on
+    pub fn handle(&self) -> EFIHandle {
+        self.handle
+    }
+}
+
+impl<'a, P: Protocol> Deref for ProtocolGuard<'a, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        self.interface
+    }
+}
+
+impl<'a, P: Protocol> Drop for ProtocolGuard<'a, P> {
+    fn drop(&mut self) {
+        // NOTE(patrik): Nothing we can do if the close fails
+        let _ = self.boot_services.close_protocol(self.handle,
+                                                  &P::GUID,
+                                                  self.agent_handle,
+                                                  self.controller_handle);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{ EFIStatus, EFIGuid, EFITime, EFIResult, TableHeader };
+use crate::memory::MemoryDescriptor;
+use crate::string::CStr16;
+
+/// The capabilities of the real time clock
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct EFITimeCapabilities {
+    /// The resolution of the clock in counts per second
+    pub resolution: u32,
+    /// The accuracy of the clock in parts per million times 1000000
+    pub accuracy: u32,
+    /// If the time below the resolution is cleared when the time is set
+    pub sets_to_zero: bool,
+}
+
+/// The type of reset to do with `reset_system`
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[repr(C)]
+pub enum EFIResetType {
+    Cold             = 0,
+    Warm             = 1,
+    Shutdown         = 2,
+    PlatformSpecific = 3,
+}
+
+/// All the function pointers for the RuntimeServices, the table is
+/// still valid after `exit_boot_services` so the kernel can use it
+#[repr(C)]
+pub struct RuntimeServices {
+    header: TableHeader,
+
+    get_time_fn: unsafe fn(time: &mut EFITime,
+                           capabilities: *mut EFITimeCapabilities)
+                    -> EFIStatus,
+    set_time_fn: unsafe fn(time: &EFITime) -> EFIStatus,
+    get_wakeup_time_fn: usize,
+    set_wakeup_time_fn: usize,
+
+    set_virtual_address_map_fn: unsafe fn(memory_map_size: usize,
+                                          descriptor_size: usize,
+                                          descriptor_version: u32,
+                                          virtual_map: *mut MemoryDescriptor)
+                                    -> EFIStatus,
+    convert_pointer_fn: usize,
+
+    get_variable_fn: unsafe fn(variable_name: *const u16,
+                               vendor_guid: &EFIGuid,
+                               attributes: *mut u32,
+                               data_size: &mut usize,
+                               data: *mut u8) -> EFIStatus,
+    get_next_variable_name_fn: unsafe fn(variable_name_size: &mut usize,
+                                         variable_name: *mut u16,
+                                         vendor_guid: &mut EFIGuid)
+                                    -> EFIStatus,
+    set_variable_fn: unsafe fn(variable_name: *const u16,
+                               vendor_guid: &EFIGuid,
+                               attributes: u32,
+                               data_size: usize,
+                               data: *const u8) -> EFIStatus,
+
+    get_next_high_monotonic_count_fn: usize,
+    reset_system_fn: unsafe fn(reset_type: EFIResetType,
+                               reset_status: EFIStatus,
+                               data_size: usize,
+                               reset_data: *const u16) -> !,
+
+    update_capsule_fn: usize,
+    query_capsule_capabilities_fn: usize,
+
+    query_variable_info_fn: usize,
+}
+
+impl RuntimeServices {
+    /// Get the current time from the real time clock
+    pub fn get_time(&self) -> EFIResult<EFITime> {
+        let mut time = EFITime::default();
+
+        let status = unsafe {
+            (self.get_time_fn)(&mut time, core::ptr::null_mut())
+        };
+
+        status.into_result("RuntimeServices::get_time")?;
+
+        Ok(time)
+    }
+
+    /// Get the capabilities of the real time clock
+    pub fn get_time_capabilities(&self) -> EFIResult<EFITimeCapabilities> {
+        let mut time = EFITime::default();
+        let mut capabilities = EFITimeCapabilities::default();
+
+        let status = unsafe {
+            (self.get_time_fn)(&mut time, &mut capabilities)
+        };
+
+        status.into_result("RuntimeServices::get_time_capabilities")?;
+
+        Ok(capabilities)
+    }
+
+    /// Set the time of the real time clock
+    pub fn set_time(&self, time: &EFITime) -> EFIResult<()> {
+        let status = unsafe {
+            (self.set_time_fn)(time)
+        };
+
+        status.into_result("RuntimeServices::set_time")
+    }
+
+    /// Read a variable in to the buffer and return the size of the data
+    /// and the attributes of the variable
+    /// NOTE(patrik): If the buffer is too small the error status is
+    /// BufferTooSmall, use `get_variable_size` to get the size
+    pub fn get_variable(&self, name: &CStr16, vendor: &EFIGuid,
+                        buffer: &mut [u8])
+        -> EFIResult<(usize, u32)>
+    {
+        let mut attributes = 0;
+        let mut data_size = buffer.len();
+
+        let status = unsafe {
+            (self.get_variable_fn)(name.as_ptr(), vendor, &mut attributes,
+                                   &mut data_size, buffer.as_mut_ptr())
+        };
+
+        status.into_result("RuntimeServices::get_variable")
+            .map_err(|err| err.with_guid(vendor))?;
+
+        Ok((data_size, attributes))
+    }
+
+    /// Get the size of the data stored in a variable
+    pub fn get_variable_size(&self, name: &CStr16, vendor: &EFIGuid)
+        -> EFIResult<usize>
+    {
+        let mut data_size = 0;
+
+        let status = unsafe {
+            (self.get_variable_fn)(name.as_ptr(), vendor,
+                                   core::ptr::null_mut(), &mut data_size,
+                                   core::ptr::null_mut())
+        };
+
+        // We expect BufferTooSmall because we didn't give the function
+        // a buffer, but a empty variable returns Success
+        if status != EFIStatus::BufferTooSmall {
+            status.into_result("RuntimeServices::get_variable_size")
+                .map_err(|err| err.with_guid(vendor))?;
+        }
+
+        Ok(data_size)
+    }
+
+    /// Get the name of the variable after the variable in `name` and
+    /// `vendor`, start with a empty name to get the first variable,
+    /// the error status is NotFound when there is no more variables
+    /// NOTE(patrik): If the buffer is too small the error status is
+    /// BufferTooSmall
+    pub fn get_next_variable_name(&self, name: &mut [u16],
+                                  vendor: &mut EFIGuid)
+        -> EFIResult<()>
+    {
+        // Make sure the name is null-terminated so the firmware don't
+        // read past the end of the buffer
+        CStr16::from_u16_until_nul(name)?;
+
+        // The size of the buffer is in bytes
+        let mut name_size = core::mem::size_of_val(name);
+
+        let status = unsafe {
+            (self.get_next_variable_name_fn)(&mut name_size,
+                                             name.as_mut_ptr(), vendor)
+        };
+
+        status.into_result("RuntimeServices::get_next_variable_name")
+    }
+
+    /// Write a variable, writing a empty buffer deletes the variable
+    pub fn set_variable(&self, name: &CStr16, vendor: &EFIGuid,
+                        attributes: u32, data: &[u8])
+        -> EFIResult<()>
+    {
+        let status = unsafe {
+            (self.set_variable_fn)(name.as_ptr(), vendor, attributes,
+                                   data.len(), data.as_ptr())
+        };
+
+        status.into_result("RuntimeServices::set_variable")
+            .map_err(|err| err.with_guid(vendor))
+    }
+
+    /// Reset the system, the status is the reason for the reset
+    pub fn reset_system(&self, reset_type: EFIResetType,
+                        reset_status: EFIStatus) -> !
+    {
+        unsafe {
+            (self.reset_system_fn)(reset_type, reset_status,
+                                   0, core::ptr::null())
+        }
+    }
+
+    /// Change the runtime services to use virtual addresses, the map
+    /// contains the descriptors of the runtime memory with the
+    /// `virtual_start` set to where the memory is mapped
+    ///
+    /// # Safety
+    /// This can only be called once after `exit_boot_services` and the
+    /// map needs to be valid for `map_size` bytes, after the call the
+    /// runtime services can only be used with the new mappings
+    pub unsafe fn set_virtual_address_map(&self,
+                                          map: *mut MemoryDescriptor,
+                                          map_size: usize,
+                                          descriptor_size: usize,
+                                          descriptor_version: u32)
+        -> EFIResult<()>
+    {
+        let status = (self.set_virtual_address_map_fn)(map_size,
+                                                       descriptor_size,
+                                                       descriptor_version,
+                                                       map);
+
+        status.into_result("RuntimeServices::set_virtual_address_map")
+    }
+}
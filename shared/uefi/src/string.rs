@@ -0,0 +1,356 @@
+use crate::{ EFIStatus, EFIError, EFIResult };
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Deref;
+
+/// Convert a character to UCS-2, the firmware strings can't have
+/// characters outside of the Basic Multilingual Plane or null characters
+fn to_ucs2(c: char, operation: &'static str) -> EFIResult<u16> {
+    let c = c as u32;
+
+    if c == 0 || c > 0xffff {
+        return Err(EFIError::new(EFIStatus::InvalidParameter, operation));
+    }
+
+    Ok(c as u16)
+}
+
+/// A borrowed null-terminated UCS-2 string, the string the firmware
+/// uses for the console, filenames and variable names
+#[derive(PartialEq, Eq, Debug)]
+#[repr(transparent)]
+pub struct CStr16([u16]);
+
+impl CStr16 {
+    /// Create a string from a slice that ends with the only null
+    /// character in the slice
+    pub fn from_u16_with_nul(chars: &[u16]) -> EFIResult<&CStr16> {
+        match chars.iter().position(|&c| c == 0) {
+            Some(length) if length == chars.len() - 1 => {
+                Ok(unsafe { Self::from_u16_with_nul_unchecked(chars) })
+            }
+            _ => Err(EFIError::new(EFIStatus::InvalidParameter,
+                                   "CStr16::from_u16_with_nul")),
+        }
+    }
+
+    /// Create a string from the start of a slice to the first null
+    /// character, the characters after it are ignored
+    pub fn from_u16_until_nul(chars: &[u16]) -> EFIResult<&CStr16> {
+        match chars.iter().position(|&c| c == 0) {
+            Some(length) => Ok(unsafe {
+                Self::from_u16_with_nul_unchecked(&chars[..=length])
+            }),
+            None => Err(EFIError::new(EFIStatus::InvalidParameter,
+                                      "CStr16::from_u16_until_nul")),
+        }
+    }
+
+    /// Create a string from a slice without checking it
+    ///
+    /// # Safety
+    /// The slice needs to end with a null character and can't have any
+    /// other null characters
+    pub unsafe fn from_u16_with_nul_unchecked(chars: &[u16]) -> &CStr16 {
+        &*(chars as *const [u16] as *const CStr16)
+    }
+
+    /// The pointer to give to the firmware
+    pub fn as_ptr(&self) -> *const u16 {
+        self.0.as_ptr()
+    }
+
+    /// The characters without the null character
+    pub fn as_slice(&self) -> &[u16] {
+        &self.0[..self.0.len() - 1]
+    }
+
+    /// The characters with the null character
+    pub fn as_slice_with_nul(&self) -> &[u16] {
+        &self.0
+    }
+
+    /// The number of characters without the null character
+    pub fn len(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    /// Check if the string only have the null character
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the characters, the characters that aren't valid
+    /// are replaced with the replacement character
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.as_slice().iter().map(|&c| {
+            core::char::from_u32(c as u32)
+                .unwrap_or(core::char::REPLACEMENT_CHARACTER)
+        })
+    }
+}
+
+impl core::fmt::Display for CStr16 {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        use core::fmt::Write;
+
+        for c in self.chars() {
+            f.write_char(c)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&CStr16> for String {
+    fn from(string: &CStr16) -> Self {
+        string.chars().collect()
+    }
+}
+
+/// A owned null-terminated UCS-2 string allocated on the heap
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CString16(Vec<u16>);
+
+impl CString16 {
+    /// Create a empty string
+    pub fn new() -> Self {
+        Self(alloc::vec![0])
+    }
+
+    /// Add a character to the end of the string
+    pub fn push(&mut self, c: char) -> EFIResult<()> {
+        let c = to_ucs2(c, "CString16::push")?;
+
+        // Put the character where the null character is and add the
+        // null character again
+        let length = self.0.len();
+        self.0[length - 1] = c;
+        self.0.push(0);
+
+        Ok(())
+    }
+
+    /// Add a string to the end of the string, the string is unchanged
+    /// if the conversion fails
+    pub fn push_str(&mut self, string: &str) -> EFIResult<()> {
+        let length = self.0.len();
+
+        for c in string.chars() {
+            if let Err(err) = self.push(c) {
+                self.0.truncate(length - 1);
+                self.0.push(0);
+
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl core::str::FromStr for CString16 {
+    type Err = EFIError;
+
+    /// Convert a Rust string to a UCS-2 string, fails if the string have
+    /// null characters or characters UCS-2 can't represent
+    fn from_str(string: &str) -> EFIResult<Self> {
+        let mut result = Self::new();
+        result.push_str(string)?;
+
+        Ok(result)
+    }
+}
+
+impl Default for CString16 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for CString16 {
+    type Target = CStr16;
+
+    fn deref(&self) -> &CStr16 {
+        unsafe { CStr16::from_u16_with_nul_unchecked(&self.0) }
+    }
+}
+
+impl From<&CStr16> for CString16 {
+    fn from(string: &CStr16) -> Self {
+        Self(string.as_slice_with_nul().to_vec())
+    }
+}
+
+impl core::fmt::Display for CString16 {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Display::fmt(self.deref(), f)
+    }
+}
+
+/// A owned null-terminated UCS-2 string on the stack, it can hold
+/// `N - 1` characters and the null character. Useful when the allocator
+/// can't be used i.e in the panic handler
+#[derive(Clone, Debug)]
+pub struct CStr16Buf<const N: usize> {
+    buffer: [u16; N],
+    length: usize,
+}
+
+impl<const N: usize> CStr16Buf<N> {
+    /// Create a empty string
+    pub fn new() -> Self {
+        // NOTE(patrik): There needs to be room for the null character
+        assert!(N > 0, "CStr16Buf needs room for the null character");
+
+        Self {
+            buffer: [0; N],
+            length: 0,
+        }
+    }
+
+    /// The number of characters that can be added to the string
+    pub fn remaining(&self) -> usize {
+        N - 1 - self.length
+    }
+
+    /// Add a character to the end of the string, the error status is
+    /// BufferTooSmall if the string is full
+    pub fn push(&mut self, c: char) -> EFIResult<()> {
+        let c = to_ucs2(c, "CStr16Buf::push")?;
+
+        if self.remaining() == 0 {
+            return Err(EFIError::new(EFIStatus::BufferTooSmall,
+                                     "CStr16Buf::push"));
+        }
+
+        self.buffer[self.length] = c;
+        self.length += 1;
+        self.buffer[self.length] = 0;
+
+        Ok(())
+    }
+
+    /// Remove all the characters
+    pub fn clear(&mut self) {
+        self.length = 0;
+        self.buffer[0] = 0;
+    }
+}
+
+impl<const N: usize> core::str::FromStr for CStr16Buf<N> {
+    type Err = EFIError;
+
+    /// Convert a Rust string to a UCS-2 string, fails if the string
+    /// doesn't fit
+    fn from_str(string: &str) -> EFIResult<Self> {
+        let mut result = Self::new();
+        for c in string.chars() {
+            result.push(c)?;
+        }
+
+        Ok(result)
+    }
+}
+
+impl<const N: usize> Default for CStr16Buf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for CStr16Buf<N> {
+    type Target = CStr16;
+
+    fn deref(&self) -> &CStr16 {
+        unsafe {
+            CStr16::from_u16_with_nul_unchecked(
+                &self.buffer[..=self.length])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn cstring16_from_str() {
+        let string: CString16 = "Potato €".parse().unwrap();
+
+        assert_eq!(string.len(), 8);
+        assert_eq!(string.as_slice_with_nul().last(), Some(&0));
+        assert_eq!(String::from(&*string), "Potato €");
+    }
+
+    #[test]
+    fn cstring16_rejects_non_bmp_and_nul() {
+        let err = "Potato 🥔".parse::<CString16>().unwrap_err();
+        assert_eq!(err.status(), EFIStatus::InvalidParameter);
+
+        let err = "a\0b".parse::<CString16>().unwrap_err();
+        assert_eq!(err.status(), EFIStatus::InvalidParameter);
+    }
+
+    #[test]
+    fn cstring16_push_str_is_unchanged_on_error() {
+        let mut string: CString16 = "abc".parse().unwrap();
+
+        assert!(string.push_str("de\0f").is_err());
+        assert_eq!(string.to_string(), "abc");
+        assert_eq!(string.as_slice_with_nul(), [0x61, 0x62, 0x63, 0]);
+    }
+
+    #[test]
+    fn from_u16_with_nul() {
+        let string = CStr16::from_u16_with_nul(&[0x61, 0x62, 0]).unwrap();
+        assert_eq!(string.as_slice(), [0x61, 0x62]);
+
+        // Interior null characters
+        assert!(CStr16::from_u16_with_nul(&[0x61, 0, 0x62, 0]).is_err());
+        assert!(CStr16::from_u16_with_nul(&[0, 0]).is_err());
+
+        // No null character
+        assert!(CStr16::from_u16_with_nul(&[0x61, 0x62]).is_err());
+        assert!(CStr16::from_u16_with_nul(&[]).is_err());
+
+        assert!(CStr16::from_u16_with_nul(&[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn from_u16_until_nul() {
+        let string = CStr16::from_u16_until_nul(&[0x61, 0, 0x62, 0])
+            .unwrap();
+        assert_eq!(string.as_slice(), [0x61]);
+
+        assert!(CStr16::from_u16_until_nul(&[0x61]).is_err());
+    }
+
+    #[test]
+    fn cstr16buf_full() {
+        let mut string: CStr16Buf<4> = "abc".parse().unwrap();
+        assert_eq!(string.remaining(), 0);
+
+        let err = string.push('d').unwrap_err();
+        assert_eq!(err.status(), EFIStatus::BufferTooSmall);
+        assert_eq!(string.to_string(), "abc");
+
+        let err = "abcd".parse::<CStr16Buf<4>>().unwrap_err();
+        assert_eq!(err.status(), EFIStatus::BufferTooSmall);
+
+        string.clear();
+        assert!(string.is_empty());
+        assert_eq!(string.as_slice_with_nul(), [0]);
+    }
+
+    #[test]
+    fn cstr16buf_rejects_non_bmp_and_nul() {
+        let mut string = CStr16Buf::<8>::new();
+
+        assert!(string.push('🥔').is_err());
+        assert!(string.push('\0').is_err());
+        assert!(string.is_empty());
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{ EFIStatus, EFIGuid, EFIError, EFIResult };
+use crate::runtime::RuntimeServices;
+use crate::string::{ CStr16, CString16 };
+
+use alloc::vec::Vec;
+use alloc::string::String;
+
+/// GUID for the variables defined by the UEFI specification
+/// i.e BootOrder and SecureBoot
+pub const GLOBAL_VARIABLE_GUID: EFIGuid =
+    EFIGuid {
+        data1: 0x8be4df61,
+        data2: 0x93ca,
+        data3: 0x11d2,
+        data4: [0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]
+    };
+
+// Flags for the attributes of a variable
+bitflags! {
+    pub struct EFIVariableAttributes: u32 {
+        const NON_VOLATILE                          = 0x00000001;
+        const BOOTSERVICE_ACCESS                    = 0x00000002;
+        const RUNTIME_ACCESS                        = 0x00000004;
+        const HARDWARE_ERROR_RECORD                 = 0x00000008;
+        const AUTHENTICATED_WRITE_ACCESS            = 0x00000010;
+        const TIME_BASED_AUTHENTICATED_WRITE_ACCESS = 0x00000020;
+        const APPEND_WRITE                          = 0x00000040;
+    }
+}
+
+/// Load option attribute for a boot entry that is active
+pub const LOAD_OPTION_ACTIVE: u32 = 0x00000001;
+/// Load option attribute for a boot entry that should be hidden
+pub const LOAD_OPTION_HIDDEN: u32 = 0x00000008;
+
+/// The largest name we accept when enumerating the variables, the
+/// firmware should never have names this long
+const MAX_NAME_LENGTH: usize = 32 * 1024;
+
+/// A variable read from the variable store
+#[derive(Clone, Debug)]
+pub struct Variable {
+    pub data: Vec<u8>,
+    pub attributes: EFIVariableAttributes,
+}
+
+/// A boot entry stored in a BootNNNN variable
+#[derive(Clone, Debug)]
+pub struct LoadOption {
+    pub attributes: u32,
+    pub description: String,
+
+    /// The raw device paths for the image the entry boots
+    pub file_path_list: Vec<u8>,
+    /// Data given to the image when the entry boots
+    pub optional_data: Vec<u8>,
+}
+
+impl LoadOption {
+    /// Parse a load option from the data of a BootNNNN variable
+    pub fn parse(data: &[u8]) -> EFIResult<Self> {
+        let error = EFIError::new(EFIStatus::CompromisedData,
+                                  "LoadOption::parse");
+
+        // The header is the attributes and the length of the file paths
+        if data.len() < 6 {
+            return Err(error);
+        }
+
+        let attributes =
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let file_path_list_length =
+            u16::from_le_bytes([data[4], data[5]]) as usize;
+
+        // The description is a null-terminated UCS-2 string
+        let mut description = Vec::new();
+        let mut offset = 6;
+        loop {
+            if offset + 2 > data.len() {
+                return Err(error);
+            }
+
+            let c = u16::from_le_bytes([data[offset], data[offset + 1]]);
+            offset += 2;
+
+            description.push(c);
+            if c == 0 {
+                break;
+            }
+        }
+        let description = CStr16::from_u16_with_nul(&description)?;
+
+        // The file paths comes after the description and the rest of
+        // the data is the optional data
+        let file_path_list_end = offset + file_path_list_length;
+        if file_path_list_end > data.len() {
+            return Err(error);
+        }
+
+        Ok(Self {
+            attributes,
+            description: String::from(description),
+
+            file_path_list: data[offset..file_path_list_end].to_vec(),
+            optional_data: data[file_path_list_end..].to_vec(),
+        })
+    }
+
+    /// Check if the entry is active
+    pub fn is_active(&self) -> bool {
+        self.attributes & LOAD_OPTION_ACTIVE != 0
+    }
+}
+
+/// A high level api for the variables, the api uses the allocator so
+/// it can only be used before `exit_boot_services`
+pub struct VariableStore<'a> {
+    runtime_services: &'a RuntimeServices,
+}
+
+impl<'a> VariableStore<'a> {
+    /// Create a new variable store
+    pub fn new(runtime_services: &'a RuntimeServices) -> Self {
+        Self {
+            runtime_services,
+        }
+    }
+
+    /// Read a variable
+    pub fn read(&self, name: &str, vendor: &EFIGuid)
+        -> EFIResult<Variable>
+    {
+        let name: CString16 = name.parse()?;
+
+        // Get the size of the variable so we can allocate the buffer
+        let size = self.runtime_services.get_variable_size(&name, vendor)?;
+        let mut data = vec![0u8; size];
+
+        let (size, attributes) =
+            self.runtime_services.get_variable(&name, vendor, &mut data)?;
+        data.truncate(size);
+
+        Ok(Variable {
+            data,
+            attributes: EFIVariableAttributes::from_bits_truncate(attributes),
+        })
+    }
+
+    /// Write a variable, the variable is created if it doesn't exist
+    pub fn write(&self, name: &str, vendor: &EFIGuid,
+                 attributes: EFIVariableAttributes, data: &[u8])
+        -> EFIResult<()>
+    {
+        let name: CString16 = name.parse()?;
+
+        self.runtime_services.set_variable(&name, vendor,
+                                           attributes.bits(), data)
+    }
+
+    /// Delete a variable
+    pub fn delete(&self, name: &str, vendor: &EFIGuid) -> EFIResult<()> {
+        let name: CString16 = name.parse()?;
+
+        self.runtime_services.set_variable(&name, vendor, 0, &[])
+    }
+
+    /// Return a iterator over the names and vendors of all the variables
+    pub fn names(&self) -> VariableNames<'a> {
+        VariableNames {
+            runtime_services: self.runtime_services,
+
+            name: vec![0u16; 64],
+            vendor: EFIGuid::new(0, 0, 0, [0; 8]),
+            done: false,
+        }
+    }
+
+    /// Read a global variable that contains a u16
+    fn read_global_u16(&self, name: &str) -> EFIResult<u16> {
+        let variable = self.read(name, &GLOBAL_VARIABLE_GUID)?;
+
+        if variable.data.len() < 2 {
+            return Err(EFIError::new(EFIStatus::CompromisedData,
+                                     "VariableStore::read_global_u16"));
+        }
+
+        Ok(u16::from_le_bytes([variable.data[0], variable.data[1]]))
+    }
+
+    /// Read a global variable that contains a u64
+    fn read_global_u64(&self, name: &str) -> EFIResult<u64> {
+        let variable = self.read(name, &GLOBAL_VARIABLE_GUID)?;
+
+        if variable.data.len() < 8 {
+            return Err(EFIError::new(EFIStatus::CompromisedData,
+                                     "VariableStore::read_global_u64"));
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&variable.data[..8]);
+
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    /// The order the firmware tries the boot entries in
+    pub fn boot_order(&self) -> EFIResult<Vec<u16>> {
+        let variable = self.read("BootOrder", &GLOBAL_VARIABLE_GUID)?;
+
+        let order = variable.data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+
+        Ok(order)
+    }
+
+    /// Change the order the firmware tries the boot entries in
+    pub fn set_boot_order(&self, order: &[u16]) -> EFIResult<()> {
+        let data: Vec<u8> = order.iter()
+            .flat_map(|entry| entry.to_le_bytes().to_vec())
+            .collect();
+
+        let attributes = EFIVariableAttributes::NON_VOLATILE |
+            EFIVariableAttributes::BOOTSERVICE_ACCESS |
+            EFIVariableAttributes::RUNTIME_ACCESS;
+
+        self.write("BootOrder", &GLOBAL_VARIABLE_GUID, attributes, &data)
+    }
+
+    /// The boot entry the firmware used to boot this time
+    pub fn boot_current(&self) -> EFIResult<u16> {
+        self.read_global_u16("BootCurrent")
+    }
+
+    /// Read the BootNNNN boot entry
+    pub fn boot_option(&self, number: u16) -> EFIResult<LoadOption> {
+        let name = format!("Boot{:04X}", number);
+        let variable = self.read(&name, &GLOBAL_VARIABLE_GUID)?;
+
+        LoadOption::parse(&variable.data)
+    }
+
+    /// Check if the firmware is booting with secure boot enabled
+    pub fn secure_boot(&self) -> EFIResult<bool> {
+        let variable = self.read("SecureBoot", &GLOBAL_VARIABLE_GUID)?;
+
+        Ok(variable.data.first() == Some(&1))
+    }
+
+    /// The features the firmware supports for `os_indications`
+    pub fn os_indications_supported(&self) -> EFIResult<u64> {
+        self.read_global_u64("OsIndicationsSupported")
+    }
+
+    /// The features the OS wants the firmware to do on the next boot
+    pub fn os_indications(&self) -> EFIResult<u64> {
+        self.read_global_u64("OsIndications")
+    }
+
+    /// Set the features the OS wants the firmware to do on the next boot
+    /// i.e boot to the firmware setup
+    pub fn set_os_indications(&self, indications: u64) -> EFIResult<()> {
+        let attributes = EFIVariableAttributes::NON_VOLATILE |
+            EFIVariableAttributes::BOOTSERVICE_ACCESS |
+            EFIVariableAttributes::RUNTIME_ACCESS;
+
+        self.write("OsIndications", &GLOBAL_VARIABLE_GUID,
+                   attributes, &indications.to_le_bytes())
+    }
+}
+
+/// A iterator over the names and vendors of all the variables
+pub struct VariableNames<'a> {
+    runtime_services: &'a RuntimeServices,
+
+    name: Vec<u16>,
+    vendor: EFIGuid,
+    done: bool,
+}
+
+impl<'a> Iterator for VariableNames<'a> {
+    type Item = EFIResult<(String, EFIGuid)>;
+
+    /// Get the next variable name from the firmware
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        loop {
+            let result = self.runtime_services
+                .get_next_variable_name(&mut self.name, &mut self.vendor);
+
+            match result {
+                Ok(()) => {
+                    let name = CStr16::from_u16_until_nul(&self.name)
+                        .map(|name| (String::from(name), self.vendor));
+
+                    return Some(name);
+                }
+
+                // Grow the buffer and try again, the buffer still
+                // contains the last name so the firmware can continue
+                Err(err) if err.status() == EFIStatus::BufferTooSmall &&
+                    self.name.len() < MAX_NAME_LENGTH =>
+                {
+                    let new_length = self.name.len() * 2;
+                    self.name.resize(new_length, 0);
+                }
+
+                // NotFound means that there is no more variables
+                Err(err) if err.status() == EFIStatus::NotFound => {
+                    self.done = true;
+                    return None;
+                }
+
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}